@@ -0,0 +1,146 @@
+extern crate walkdir;
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::ffi::OsStr;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use deploy::DeployError;
+
+// An archive is made by deploying into a scratch target root, recording
+// commands rather than running them, and then writing out that tree
+// as a ustar archive, which any tar(1) can unpack from /
+
+// The commands that would have run are kept within the archive itself,
+// so unpack to /.jann/commands
+pub const COMMANDS: &str = ".jann/commands";
+
+// The destinations deployed into the stage, by this and any included Jannfile
+const DEPLOYED: &str = ".jann/deployed";
+
+// record - Note a command that would have run, and where
+pub fn record(stage: &Path, dir: &Path, command: &str) -> Result<(), DeployError> {
+    let file = stage.join(COMMANDS);
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut commands = fs::OpenOptions::new().create(true).append(true).open(&file)?;
+    writeln!(commands, "cd {}", quote(dir))?;
+    writeln!(commands, "{}", command)?;
+    Ok(())
+}
+
+// quote - Quote a path for the shell, whatever quotes it holds itself
+pub fn quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "'\\''"))
+}
+
+// note - Note destinations deployed into the stage, which alone are archived
+pub fn note(stage: &Path, deployed: &[PathBuf]) -> Result<(), DeployError> {
+    let file = stage.join(DEPLOYED);
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut list = fs::OpenOptions::new().create(true).append(true).open(&file)?;
+    for path in deployed {
+        list.write_all(path.as_os_str().as_bytes())?;
+        list.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn too_long(name: &[u8]) -> DeployError {
+    DeployError { source: String::from("Archive"),
+                  message: format!("Path is too long to archive: {}", String::from_utf8_lossy(name)) }
+}
+
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+// header - Build the header block for an entry
+// Names longer than 100 bytes are split across the prefix field at a '/'
+fn header(name: &[u8], meta: &fs::Metadata, kind: u8, link: &[u8]) -> Result<[u8; 512], DeployError> {
+    let mut block = [0u8; 512];
+
+    let (prefix, name) = if name.len() <= 100 {
+        (&name[..0], name)
+    }
+    else {
+        let split = name.iter().enumerate()
+                        .filter(|&(i, b)| *b == b'/' && i <= 155 && name.len() - i - 1 <= 100)
+                        .map(|(i, _)| i)
+                        .next()
+                        .ok_or_else(|| too_long(name))?;
+        (&name[..split], &name[split + 1..])
+    };
+    if link.len() > 100 {
+        return Err(too_long(link));
+    }
+
+    block[..name.len()].copy_from_slice(name);
+    octal(&mut block[100..108], (meta.mode() & 0o7777) as u64);
+    octal(&mut block[108..116], meta.uid() as u64);
+    octal(&mut block[116..124], meta.gid() as u64);
+    octal(&mut block[124..136], if kind == b'0' { meta.len() } else { 0 });
+    octal(&mut block[136..148], meta.mtime().max(0) as u64);
+    block[156] = kind;
+    block[157..157 + link.len()].copy_from_slice(link);
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix);
+
+    // The checksum is taken with its own field filled with spaces
+    block[148..156].copy_from_slice(b"        ");
+    let sum: u32 = block.iter().map(|b| *b as u32).sum();
+    octal(&mut block[148..155], sum as u64);
+    Ok(block)
+}
+
+// write - Archive whatever was deployed within a stage, by its path beneath the stage
+// Directories which were only made along the way are left out, so that
+// unpacking the archive never changes the modes or owners of, say, /etc
+pub fn write(stage: &Path, out: &Path) -> Result<(), DeployError> {
+    let mut deployed: Vec<PathBuf> = match fs::read(stage.join(DEPLOYED)) {
+        Ok(data) => data.split(|b| *b == b'\n')
+                        .filter(|l| !l.is_empty())
+                        .map(|l| PathBuf::from(OsStr::from_bytes(l)))
+                        .collect(),
+        Err(_) => vec![],
+    };
+    deployed.push(stage.join(COMMANDS));
+
+    let mut tar = BufWriter::new(fs::File::create(out)?);
+    let walk = walkdir::WalkDir::new(stage).follow_links(false).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+    for entry in walk.into_iter().skip(1) {
+        let entry = entry?;
+        if !deployed.iter().any(|d| entry.path().starts_with(d)) {
+            continue;
+        }
+        let meta = entry.path().symlink_metadata()?;
+        let mut name = entry.path().strip_prefix(stage).unwrap().as_os_str().as_bytes().to_vec();
+
+        if meta.file_type().is_dir() {
+            name.push(b'/');
+            tar.write_all(&header(&name, &meta, b'5', b"")?)?;
+        }
+        else if meta.file_type().is_symlink() {
+            let link = fs::read_link(entry.path())?;
+            tar.write_all(&header(&name, &meta, b'2', link.as_os_str().as_bytes())?)?;
+        }
+        else if meta.file_type().is_file() {
+            tar.write_all(&header(&name, &meta, b'0', b"")?)?;
+            let data = fs::read(entry.path())?;
+            tar.write_all(&data)?;
+            let pad = (512 - data.len() % 512) % 512;
+            tar.write_all(&vec![0u8; pad])?;
+        }
+    }
+    // Two empty blocks mark the end of the archive
+    tar.write_all(&[0u8; 1024])?;
+    tar.flush()?;
+    Ok(())
+}
@@ -15,8 +15,14 @@ pub enum Reference {
     // --execute
     PIPELINE(String),
 
-    // --allow, --forbid
+    // --allow, --forbid, --preserve
     FLAG(String),
+
+    // --run
+    RUN(String),
+
+    // --target-root, --archive
+    PATH(String),
 }
 
 pub type Switches = Vec<(String, Vec<Reference>)>; 
@@ -26,11 +32,13 @@ pub enum Command {
     HELP { code: i32 },
     DO_STDIN { switches: Switches },
     DO_FILE { switches: Switches, file: String },
+    RESTORE { run: String },
+    ROLLBACK { run: Option<String> },
 }
 
 fn is_verb(s: &str) -> bool {
     match s {
-        "execute" | "allow" | "forbid" | "enable" | "disable" => true,
+        "execute" | "allow" | "forbid" | "enable" | "disable" | "plan" | "diff" | "interactive" | "prune" | "status" | "capture" | "preserve" | "run" | "target-root" | "archive" => true,
         _ => false,
     }
 }
@@ -61,7 +69,13 @@ fn parse_switches(args : env::Args) -> Result<Switches, Command> {
                     if verb == "execute" {
                         cur_args.push(Reference::PIPELINE(arg));
                     }
-                    else if verb == "allow" || verb == "forbid" {
+                    else if verb == "run" {
+                        cur_args.push(Reference::RUN(arg));
+                    }
+                    else if verb == "target-root" || verb == "archive" {
+                        cur_args.push(Reference::PATH(arg));
+                    }
+                    else if verb == "allow" || verb == "forbid" || verb == "preserve" {
                         cur_args.push(Reference::FLAG(arg))
                     }
                     else if verb == "enable" || verb == "disable" {
@@ -109,16 +123,41 @@ impl Command {
     pub fn new() -> Command {
         let mut args = std::env::args();
         let jann_bin = args.next();
-        match args.next() {
+        let mut first = args.next();
+
+        // jann status <Jannfile> is shorthand for jann <Jannfile> --status,
+        // and likewise for capture
+        let mode = match first.as_ref().map(|a| a.as_ref()) {
+            Some("status") => Some("status"),
+            Some("capture") => Some("capture"),
+            _ => None,
+        };
+        if mode.is_some() {
+            first = args.next();
+        }
+
+        match first {
             Some(ref arg) => {
                 match arg.as_ref() {
                     "--version" => { return Command::VERSION { code: 0 }; },
                     "--help" => { return Command::HELP { code: 64 }; },
+                    "restore" => {
+                        return match args.next() {
+                            Some(run) => Command::RESTORE { run },
+                            None => Command::HELP { code: 64 },
+                        };
+                    },
+                    "rollback" => {
+                        return Command::ROLLBACK { run: args.next() };
+                    },
                     _ => (),
                 }
 
                 match parse_switches(args) {
-                    Ok(sw) => {
+                    Ok(mut sw) => {
+                        if let Some(mode) = mode {
+                            sw.push((String::from(mode), vec![]));
+                        }
                         if arg == "--" {
                             return Command::DO_STDIN { switches: sw };
                         }
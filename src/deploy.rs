@@ -1,12 +1,33 @@
 extern crate walkdir;
+extern crate libc;
 
 use std::path::{Path, PathBuf, Component};
+use std::ffi::{CString, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs as unix_fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::io::BufRead;
 use std::fs;
+use std::io;
+use std::cmp;
 use std::convert;
 
+use state;
+use diff;
+use glob;
+use template;
+
 // Deploy Options
 // - Whether to overwrite {Files, Dirs} w/ {Files, Dirs}
 // - Whether to create INTERmediary directories
+// - Whether to only PLAN the deployment, leaving the filesystem untouched
+// - Whether to show a DIFF of each overwrite before it is made
+// - Whether to ASK on the terminal before each overwrite is made
+// - Whether to PRUNE destinations that the Jannfile no longer deploys
+// - Whether to only report the STATUS of destinations against the bundle
+// - Whether to CAPTURE destinations back into the bundle instead
+// - Whether to ARCHIVE the deployment, staging it under the target root
+// - What to PRESERVE of each entity copied, besides its contents
 #[derive(Clone, Copy, Debug)]
 pub struct DepOpt {
     pub OW_FF: bool,
@@ -14,11 +35,24 @@ pub struct DepOpt {
     pub OW_FD: bool,
     pub OW_DF: bool,
     pub INTER: bool,
+    pub PLAN: bool,
+    pub DIFF: bool,
+    pub ASK: bool,
+    pub PRUNE: bool,
+    pub STATUS: bool,
+    pub CAPTURE: bool,
+    pub ARCHIVE: bool,
+    pub PRESERVE: Preserve,
 }
 
 impl DepOpt {
     // check - Determine if an overwrite may take place based on these options
-    fn check(&self, src_ent: &Entity, dst_ent: &Entity) -> bool {
+    // A merge of one directory into another overwrites nothing by itself,
+    // rather each of the entities it carries is checked in turn
+    fn check(&self, src_ent: &Entity, dst_ent: &Entity, method: Method) -> bool {
+        if (method == Method::MERGE || method == Method::MIRROR) && *src_ent == Entity::DIR && *dst_ent == Entity::DIR {
+            return true;
+        }
         match *src_ent {
             Entity::FILE => {
                 match *dst_ent {
@@ -54,16 +88,205 @@ impl DepOpt {
         if self.INTER { allows.push("inter"); } else { forbids.push("inter"); }
 
         allows.extend(forbids);
+        if self.PLAN { allows.push("--plan"); }
+        if self.DIFF { allows.push("--diff"); }
+        if self.ASK { allows.push("--interactive"); }
+        if self.PRUNE { allows.push("--prune"); }
+        if self.STATUS { allows.push("--status"); }
+        if self.CAPTURE { allows.push("--capture"); }
+        if self.ARCHIVE { allows.push("--archive"); }
+
+        let mut preserves = vec!["--preserve"];
+        if self.PRESERVE.LINKS { preserves.push("links"); }
+        if self.PRESERVE.MODES { preserves.push("modes"); }
+        if self.PRESERVE.TIMES { preserves.push("times"); }
+        if preserves.len() > 1 { allows.extend(preserves); }
         allows
     }
 }
 
+// Preserve - What copies carry over from their source, besides contents
+// - Whether symLINKS are copied as links, rather than followed
+// - Whether directory MODES are kept exactly, rather than masked by the umask as cp(1) does
+// - Whether modification TIMES are kept, rather than set to the time of copying
+#[derive(Clone, Copy, Debug)]
+pub struct Preserve {
+    pub LINKS: bool,
+    pub MODES: bool,
+    pub TIMES: bool,
+}
+
+impl Preserve {
+    pub const NONE: Preserve = Preserve { LINKS: false, MODES: false, TIMES: false };
+    pub const ALL: Preserve = Preserve { LINKS: true, MODES: true, TIMES: true };
+}
+
+// Attrs - The mode and ownership a statement asks its destination to have
+// Only the destination itself is affected, not anything within it
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Attrs {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Attrs {
+    // of - The attributes an entity has now
+    pub fn of(path: &Path) -> Result<Attrs, DeployError> {
+        let meta = fs::symlink_metadata(path)?;
+        Ok(Attrs { mode: Some(meta.mode() & 0o7777), uid: Some(meta.uid()), gid: Some(meta.gid()) })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.uid.is_none() && self.gid.is_none()
+    }
+
+    // matches - Determine whether an entity already has these attributes
+    pub fn matches(&self, path: &Path) -> Result<bool, DeployError> {
+        if self.is_empty() {
+            return Ok(true);
+        }
+        let now = Attrs::of(path)?;
+        // Links have no mode of their own to set
+        let link = fs::symlink_metadata(path)?.file_type().is_symlink();
+        Ok(self.mode.is_none_or(|m| link || now.mode == Some(m))
+           && self.uid.is_none_or(|u| now.uid == Some(u))
+           && self.gid.is_none_or(|g| now.gid == Some(g)))
+    }
+
+    // apply - Give an entity these attributes
+    // Ownership goes first, since changing it can clear setuid bits
+    pub fn apply(&self, path: &Path) -> Result<(), DeployError> {
+        if self.uid.is_some() || self.gid.is_some() {
+            unix_fs::lchown(path, self.uid, self.gid)?;
+        }
+        if let Some(mode) = self.mode {
+            if !fs::symlink_metadata(path)?.file_type().is_symlink() {
+                fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// user - Find the uid for a user name, or numeric id
+pub fn user(name: &str) -> Option<u32> {
+    if let Ok(uid) = name.parse() {
+        return Some(uid);
+    }
+    let name = ::std::ffi::CString::new(name).ok()?;
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() { None } else { Some(unsafe { (*entry).pw_uid }) }
+}
+
+// group - Find the gid for a group name, or numeric id
+pub fn group(name: &str) -> Option<u32> {
+    if let Ok(gid) = name.parse() {
+        return Some(gid);
+    }
+    let name = ::std::ffi::CString::new(name).ok()?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() { None } else { Some(unsafe { (*entry).gr_gid }) }
+}
+
+// Method - How a source is laid onto an extant destination
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Method {
+    REPLACE, // The destination is replaced wholesale
+    MERGE,   // A source tree is overlaid onto a destination tree
+    LINK,    // The destination is replaced by a link to the source
+    MIRROR,  // A destination tree is brought in line with a source tree in place
+}
+
+// Only - Which destinations a statement deploys to, by whether they exist
+// Merges apply this to each of the entities they carry
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Only {
+    ANY,    // Every destination, extant or not
+    CREATE, // Only missing destinations, which are seeded and then left alone
+    UPDATE, // Only destinations which already exist
+}
+
+// Outcome - What a deployment did to its destination
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    CREATED,
+    UPDATED,
+    UNCHANGED,
+    SKIPPED,
+    REMOVED,
+    CREATE_ONLY, // Passed over, since the destination exists
+    UPDATE_ONLY, // Passed over, since the destination is missing
+}
+
+impl Outcome {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Outcome::CREATED   => "created",
+            Outcome::UPDATED   => "updated",
+            Outcome::UNCHANGED => "unchanged",
+            Outcome::SKIPPED   => "skipped",
+            Outcome::REMOVED   => "removed",
+            Outcome::CREATE_ONLY => "create only",
+            Outcome::UPDATE_ONLY => "update only",
+        }
+    }
+}
+
+// Drift - How a destination compares with its bundle source
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Drift {
+    MISSING,  // There is nothing at the destination
+    MODIFIED, // The destination differs from the source
+    RETYPED,  // A file stands where a directory belongs, or vice versa
+    INSYNC,   // The destination matches the source
+    PRESENT,  // Something stands where nothing belongs
+}
+
+impl Drift {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Drift::MISSING  => "missing",
+            Drift::MODIFIED => "modified",
+            Drift::RETYPED  => "retyped",
+            Drift::INSYNC   => "in sync",
+            Drift::PRESENT  => "present",
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum Entity {
     FILE,
     DIR,
 }
 
+impl Entity {
+    fn name(&self) -> &'static str {
+        match *self {
+            Entity::FILE => "file",
+            Entity::DIR  => "dir",
+        }
+    }
+
+    fn noun(&self) -> &'static str {
+        match *self {
+            Entity::FILE => "file",
+            Entity::DIR  => "directory",
+        }
+    }
+}
+
+// replacement - Describe an overwrite of one kind of entity by another
+fn replacement(dst_ent: &Entity, src_ent: &Entity) -> String {
+    format!("{} replaced by {}", dst_ent.noun(), src_ent.noun())
+}
+
+// plan - Report an action that a deployment would take
+pub fn plan(action: &str, ent: &Entity, path: &Path) {
+    println!("[   Plan] {:>7} {:<4} {:?}", action, ent.name(), path);
+}
+
 // EndPtr - Points to last extant entity in a path
 #[derive(Debug)]
 struct EndPtr {
@@ -73,12 +296,19 @@ struct EndPtr {
 }
 
 // scout - Determine EndPtr for Destination path
-fn scout(dst_cmps: &Vec<Component>) -> EndPtr {
+// Links along the way are followed, but a link at the very end of the
+// path is treated as a file in its own right when it is to be replaced
+// by another link, or leads nowhere. Copies into a link to a directory
+// go into that directory, as they always have
+fn scout(dst_cmps: &Vec<Component>, method: Method) -> EndPtr {
     let mut scout_path = PathBuf::new();
     let mut entity = Entity::DIR;
     for (i, cmp) in dst_cmps.iter().enumerate() {
         scout_path.push(cmp);
-        if scout_path.is_file() {
+        let end_link = i + 1 == dst_cmps.len() && fs::symlink_metadata(&scout_path)
+                                                      .map(|m| m.file_type().is_symlink())
+                                                      .unwrap_or(false);
+        if (end_link && (method == Method::LINK || !scout_path.exists())) || scout_path.is_file() {
             entity = Entity::FILE;
         }
         else if scout_path.is_dir() {
@@ -106,7 +336,7 @@ pub struct DeployError {
 }
 
 impl DeployError {
-    fn locked(source: &'static str, message: &'static str) -> DeployError {
+    pub fn locked(source: &'static str, message: &'static str) -> DeployError {
         DeployError { source: String::from(source), message: String::from(message) }
     }
 }
@@ -125,70 +355,412 @@ impl convert::From<std::io::Error> for DeployError {
     }
 }
 
-fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), DeployError> {
+fn umask() -> u32 {
+    unsafe {
+        let mask = libc::umask(0o022);
+        libc::umask(mask);
+        mask as u32
+    }
+}
+
+// mode_for - The mode a copy of an entity should end up with
+fn mode_for(meta: &fs::Metadata, keep: Preserve) -> u32 {
+    let mode = meta.permissions().mode() & 0o7777;
+    // Files keep their whole mode, setuid bits and all, as they always have
+    if keep.MODES || !meta.is_dir() {
+        mode
+    }
+    else {
+        0o777 & !umask()
+    }
+}
+
+fn set_times(path: &Path, meta: &fs::Metadata) -> Result<(), DeployError> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| DeployError::locked("Deploy", "Path contains a nul byte"))?;
+    let times = [
+        libc::timespec { tv_sec: meta.atime() as libc::time_t, tv_nsec: meta.atime_nsec() as _ },
+        libc::timespec { tv_sec: meta.mtime() as libc::time_t, tv_nsec: meta.mtime_nsec() as _ },
+    ];
+    let res = unsafe {
+        libc::utimensat(libc::AT_FDCWD, c_path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+    };
+    if res != 0 {
+        return Err(DeployError::from(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+// copy_meta - Carry the mode and times of an entity over to its copy
+pub fn copy_meta(meta: &fs::Metadata, dst: &Path, keep: Preserve) -> Result<(), DeployError> {
+    if !meta.file_type().is_symlink() {
+        fs::set_permissions(dst, fs::Permissions::from_mode(mode_for(meta, keep)))?;
+    }
+    if keep.TIMES {
+        set_times(dst, meta)?;
+    }
+    Ok(())
+}
+
+// copy_entity - Copy a file or link, or create an empty directory in place of one
+// A directory must have copy_meta applied once its contents are in place
+pub fn copy_entity(src: &Path, dst: &Path, keep: Preserve) -> Result<(), DeployError> {
+    let meta = if keep.LINKS { fs::symlink_metadata(src)? } else { fs::metadata(src)? };
+    if meta.file_type().is_symlink() {
+        unix_fs::symlink(fs::read_link(src)?, dst)?;
+    }
+    else if meta.is_dir() {
+        fs::create_dir(dst)?;
+        return Ok(());
+    }
+    else {
+        fs::copy(src, dst)?;
+    }
+    copy_meta(&meta, dst, keep)
+}
+
+// copy_dir - Copy a tree, keeping as much of each entity as asked
+// Directories are finished last, deepest first, so that neither writing
+// their contents nor a read-only mode gets in the way
+fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q, keep: Preserve) -> Result<(), DeployError> {
     let src_path = src.as_ref();
     let dst_path = dst.as_ref();
-     
-    for entry in walkdir::WalkDir::new(src_path) {
+    let mut dirs = vec![];
+
+    // A linked tree is copied as the link alone
+    if keep.LINKS && fs::symlink_metadata(src_path)?.file_type().is_symlink() {
+        return copy_entity(src_path, dst_path, keep);
+    }
+
+    for entry in walkdir::WalkDir::new(src_path).follow_links(!keep.LINKS) {
         let entry = entry?;
         let path = entry.path();
         let linked = dst_path.join(path.strip_prefix(src_path).unwrap());
-        if path.is_file() {
-            fs::copy(path, &linked)?;
+        copy_entity(path, &linked, keep)?;
+        if entry.file_type().is_dir() {
+            dirs.push((entry.metadata()?, linked));
+        }
+    }
+    for (meta, linked) in dirs.iter().rev() {
+        copy_meta(meta, linked, keep)?;
+    }
+    Ok(())
+}
+
+// merge_dir - Overlay one tree onto another
+// Each entity in the source is deployed in turn, with directories that
+// already exist at the destination descended into rather than replaced.
+// Anything in the destination that the source does not carry is untouched.
+fn merge_dir(src: &Path, dst: &Path, opt: DepOpt, only: Only, run: &mut state::Run) -> Result<Outcome, DeployError> {
+    let mut outcome = Outcome::UNCHANGED;
+    let mut walk = walkdir::WalkDir::new(src).min_depth(1).into_iter();
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        let path = entry.path();
+        let linked = dst.join(path.strip_prefix(src).unwrap());
+        let sub_outcome = if path.is_dir() {
+            if linked.is_dir() {
+                continue;
+            }
+            walk.skip_current_dir();
+            deploy(path.to_path_buf(), Entity::DIR, linked, opt, Method::REPLACE, only, &Attrs::default(), run)?
         }
         else {
-            fs::create_dir_all(&linked)?;
+            deploy(path.to_path_buf(), Entity::FILE, linked, opt, Method::REPLACE, only, &Attrs::default(), run)?
+        };
+        if sub_outcome == Outcome::CREATED || sub_outcome == Outcome::UPDATED {
+            outcome = Outcome::UPDATED;
+        }
+    }
+    Ok(outcome)
+}
+
+// extraneous - Find whatever a destination tree holds that its source tree does not
+// Excluded entities are not the source's to account for, so are passed over,
+// and a directory with nothing like it in the source is found as a whole
+fn extraneous(src: &Path, dst: &Path, exclude: &glob::Exclude) -> Result<Vec<PathBuf>, DeployError> {
+    let mut extra = vec![];
+    let mut walk = walkdir::WalkDir::new(dst).min_depth(1).sort_by(|a, b| a.file_name().cmp(b.file_name()))
+                                             .into_iter().filter_entry(|e| {
+        !exclude.excludes(e.path().strip_prefix(dst).unwrap(), e.file_type().is_dir())
+    });
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        let counterpart = src.join(entry.path().strip_prefix(dst).unwrap());
+        if fs::symlink_metadata(&counterpart).is_err() {
+            extra.push(entry.path().to_path_buf());
+        }
+        else if counterpart.is_dir() {
+            continue;
+        }
+        // Anything within a directory that the source replaces goes with it
+        if entry.file_type().is_dir() {
+            walk.skip_current_dir();
         }
     }
+    Ok(extra)
+}
+
+// same_bytes - Compare the contents of two files of equal length
+fn same_bytes(a: &Path, b: &Path) -> Result<bool, DeployError> {
+    let mut a = io::BufReader::new(fs::File::open(a)?);
+    let mut b = io::BufReader::new(fs::File::open(b)?);
+    loop {
+        let n = {
+            let a_buf = a.fill_buf()?;
+            let b_buf = b.fill_buf()?;
+            if a_buf.is_empty() || b_buf.is_empty() {
+                return Ok(a_buf.is_empty() && b_buf.is_empty());
+            }
+            let n = cmp::min(a_buf.len(), b_buf.len());
+            if a_buf[..n] != b_buf[..n] {
+                return Ok(false);
+            }
+            n
+        };
+        a.consume(n);
+        b.consume(n);
+    }
+}
+
+// identical - Determine whether a destination already matches its source,
+// in which case deploying it would change nothing
+// A mode asked for by the statement stands in for that of the source
+fn identical(src: &Path, dst: &Path, keep: Preserve, attrs: &Attrs) -> Result<bool, DeployError> {
+    let src_meta = if keep.LINKS { fs::symlink_metadata(src)? } else { fs::metadata(src)? };
+    let dst_meta = fs::symlink_metadata(dst)?;
+
+    if src_meta.file_type().is_symlink() {
+        return Ok(dst_meta.file_type().is_symlink() && fs::read_link(src)? == fs::read_link(dst)?);
+    }
+    if keep.TIMES && !src_meta.is_dir() && src_meta.modified()? != dst_meta.modified()? {
+        return Ok(false);
+    }
+
+    if src_meta.is_file() {
+        // Copies carry their permissions with them, so those must match too
+        Ok(dst_meta.is_file()
+           && src_meta.len() == dst_meta.len()
+           && attrs.mode.unwrap_or_else(|| mode_for(&src_meta, keep)) == dst_meta.permissions().mode() & 0o7777
+           && same_bytes(src, dst)?)
+    }
+    else if src_meta.is_dir() && dst_meta.is_dir() {
+        if keep.MODES && attrs.mode.is_none() && mode_for(&src_meta, keep) != dst_meta.permissions().mode() & 0o7777 {
+            return Ok(false);
+        }
+        let list = |dir: &Path| -> Result<Vec<OsString>, DeployError> {
+            let mut names = vec![];
+            for entry in fs::read_dir(dir)? {
+                names.push(entry?.file_name());
+            }
+            names.sort();
+            Ok(names)
+        };
+        let names = list(src)?;
+        if names != list(dst)? {
+            return Ok(false);
+        }
+        for name in names {
+            if !identical(&src.join(&name), &dst.join(&name), keep, &Attrs::default())? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+    else {
+        Ok(false)
+    }
+}
+
+// relocate - Move an entity, copying it if it must cross filesystems
+pub fn relocate(src: &Path, dst: &Path) -> Result<(), DeployError> {
+    if fs::rename(src, dst).is_ok() {
+        return Ok(());
+    }
+    if fs::symlink_metadata(src)?.is_dir() {
+        copy_dir(src, dst, Preserve::ALL)?;
+        fs::remove_dir_all(src)?;
+    }
+    else {
+        copy_entity(src, dst, Preserve::ALL)?;
+        fs::remove_file(src)?;
+    }
     Ok(())
 }
 
+// sibling - A hidden path next to the given one, used to stage changes
+pub fn sibling(path: &Path, tag: &str) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".");
+    name.push(tag);
+    path.with_file_name(name)
+}
 
-// Deploy from source to destination based on options
-pub fn deploy(src: PathBuf, src_ent: Entity, dst: PathBuf, opt: DepOpt) -> Result<(), DeployError> {
-    if option_env!("JANN_MOSTLY_HARMLESS") == Some("1") {
-        println!("{:?} => {:?}\n...as {:?}\n... with {:?}", src, dst, src_ent, opt);
+// exchange - Atomically swap two entities with renameat2(2)
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+    let res = unsafe {
+        libc::syscall(libc::SYS_renameat2,
+                      libc::AT_FDCWD, a.as_ptr(),
+                      libc::AT_FDCWD, b.as_ptr(),
+                      libc::RENAME_EXCHANGE)
+    };
+    if res == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange(_a: &Path, _b: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "Atomic exchange is unsupported"))
+}
+
+// swap - Swap two entities, so that each path always names one or the other
+// Where the filesystem cannot exchange atomically we fall back on renames,
+// which leave the second path empty for only a moment
+pub fn swap(a: &Path, b: &Path) -> Result<(), DeployError> {
+    if exchange(a, b).is_ok() {
         return Ok(());
     }
-    let src_cmps: Vec<Component> = src.components().collect();
+    let aside = sibling(b, "jann-aside");
+    fs::rename(b, &aside)?;
+    fs::rename(a, b)?;
+    fs::rename(&aside, a)?;
+    Ok(())
+}
+
+// stage - Copy (or link) the source to a hidden sibling of the destination
+fn stage(src: &Path, src_ent: &Entity, dst: &Path, method: Method, keep: Preserve, attrs: &Attrs, run: &mut state::Run) -> Result<PathBuf, DeployError> {
+    let staged = sibling(dst, "jann-stage");
+
+    // Clear away anything left behind by an earlier run that died
+    match fs::symlink_metadata(&staged) {
+        Ok(ref meta) if meta.is_dir() => { fs::remove_dir_all(&staged)?; },
+        Ok(_) => { fs::remove_file(&staged)?; },
+        Err(_) => {},
+    }
+
+    run.create(&staged)?;
+    match (method, src_ent) {
+        (Method::LINK, _) => { unix_fs::symlink(src, &staged)?; },
+        (_, &Entity::FILE) => { copy_entity(src, &staged, keep)?; },
+        (_, &Entity::DIR) => { copy_dir(src, &staged, keep)?; },
+    }
+    attrs.apply(&staged)?;
+    Ok(staged)
+}
+
+// make_dirs - Create each missing directory leading up to and including a path
+fn make_dirs(path: &Path, run: &mut state::Run) -> Result<(), DeployError> {
+    if path.is_dir() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        make_dirs(parent, run)?;
+    }
+    run.mkdir(path)
+}
+
+// Deploy from source to destination based on options
+// When planning, every decision is made as normal but only reported
+// Anything overwritten is first backed up, and all changes are journaled
+// The source is staged beside the destination and then swapped into place,
+// so the destination never goes missing or holds a partial copy
+#[allow(clippy::too_many_arguments)]
+pub fn deploy(src: PathBuf, src_ent: Entity, dst: PathBuf, opt: DepOpt, method: Method, only: Only, attrs: &Attrs, run: &mut state::Run) -> Result<Outcome, DeployError> {
     let dst_cmps: Vec<Component> = dst.components().collect();
-    let dst_ptr = scout(&dst_cmps);
+    let dst_ptr = scout(&dst_cmps, method);
+
+    // Every destination is noted for the manifest, except that merges
+    // are accounted for by the entities they carry
+    let merging = (method == Method::MERGE || method == Method::MIRROR) && dst_ptr.full
+                  && dst_ptr.entity == Entity::DIR && src_ent == Entity::DIR;
+    if !merging {
+        run.produce(&dst);
+    }
+
+    // A destination passed over is still the statement's, so was noted above
+    if !merging && only == Only::CREATE && dst_ptr.full {
+        if opt.PLAN { plan("exists", &dst_ptr.entity, &dst); }
+        return Ok(Outcome::CREATE_ONLY);
+    }
+    if only == Only::UPDATE && !dst_ptr.full {
+        if opt.PLAN { plan("absent", &src_ent, &dst); }
+        return Ok(Outcome::UPDATE_ONLY);
+    }
+
+    // A link that already points at the source need not be made again
+    if method == Method::LINK && fs::read_link(&dst).map(|t| t == src).unwrap_or(false) && attrs.matches(&dst)? {
+        if opt.PLAN { plan("keep", &src_ent, &dst); }
+        return Ok(Outcome::UNCHANGED);
+    }
+
+    // Plans name the source that each destination will be made from
+    let whence = |how: &'static str| if method == Method::LINK { "link to" } else { how };
 
     if dst_ptr.full {
-        let viable = opt.check(&src_ent, &dst_ptr.entity);
-        if viable {
-            match &dst_ptr.entity {
-                Entity::FILE => {
-                    fs::remove_file(&dst)?; //.expect("Could not remove destination file");
-                },
-                Entity::DIR => {
-                    fs::remove_dir_all(&dst)?; //.expect("Could not remove destination directory");
-                }
+        // Re-pointing a link replaces one link with another, wherever either leads
+        let relink = method == Method::LINK && fs::symlink_metadata(&dst).map(|m| m.file_type().is_symlink()).unwrap_or(false);
+        let viable = if relink { opt.OW_FF } else { opt.check(&src_ent, &dst_ptr.entity, method) };
+        if merging {
+            if opt.PLAN { plan(if method == Method::MIRROR { "mirror" } else { "merge" }, &src_ent, &dst); }
+            let outcome = merge_dir(&src, &dst, opt, only, run)?;
+            // A merged directory stays where it is, so takes its attributes in place
+            if attrs.matches(&dst)? {
+                return Ok(outcome);
             }
-
-            match &src_ent {
-                Entity::FILE => {
-                    fs::copy(&src, &dst)?;
-                },
-                Entity::DIR => {
-                    copy_dir(&src, &dst)?;
-                }
+            if opt.PLAN {
+                plan("set", &Entity::DIR, &dst);
+                return Ok(outcome);
+            }
+            run.attrs(&dst, attrs)?;
+            return Ok(Outcome::UPDATED);
+        }
+        else if method != Method::LINK && src_ent == dst_ptr.entity && identical(&src, &dst, opt.PRESERVE, attrs)? && attrs.matches(&dst)? {
+            // Leaving identical entities be spares their timestamps and any watchers
+            if opt.PLAN { plan("keep", &src_ent, &dst); }
+            return Ok(Outcome::UNCHANGED);
+        }
+        else if viable && opt.PLAN {
+            if opt.DIFF { diff::show(&dst, &src); }
+            plan("replace", &dst_ptr.entity, &dst);
+            plan(whence("with"), &src_ent, &src);
+        }
+        else if viable {
+            if opt.DIFF { diff::show(&dst, &src); }
+            if opt.ASK && !run.confirm(&dst, &replacement(&dst_ptr.entity, &src_ent))? {
+                return Ok(Outcome::SKIPPED);
             }
+            let staged = stage(&src, &src_ent, &dst, method, opt.PRESERVE, attrs, run)?;
+            run.swap(&staged, &dst)?;
+            run.backup(&staged, &dst)?;
         }
         else {
+            if opt.PLAN { plan("refuse", &dst_ptr.entity, &dst); }
             return Err(DeployError::locked("Deploy", "Options disallow this copy"));
         }
     }
     else {
+        let mut ow_path = PathBuf::new();
+        for c in dst_cmps.iter().take(dst_ptr.ptr) {
+            ow_path.push(c);
+        }
+
         if dst_ptr.entity == Entity::FILE {
-            if !opt.OW_FD { return Err(DeployError::locked("Deploy", "Options disallow overwriting files with directories.")) }
+            if !opt.OW_FD {
+                if opt.PLAN { plan("refuse", &Entity::FILE, &ow_path); }
+                return Err(DeployError::locked("Deploy", "Options disallow overwriting files with directories."))
+            }
 
-            let mut ow_path = PathBuf::new();
-            for c in dst_cmps.iter().take(dst_ptr.ptr) {
-                ow_path.push(c);
+            if opt.PLAN {
+                plan("remove", &Entity::FILE, &ow_path);
+            }
+            else if opt.ASK && !run.confirm(&ow_path, &replacement(&Entity::FILE, &Entity::DIR))? {
+                return Ok(Outcome::SKIPPED);
             }
-            if ow_path.is_file() { // should always be true
-                fs::remove_file(&ow_path)?; //.expect("Could not remove clashing file"); 
+            else if ow_path.is_file() { // should always be true
+                run.backup(&ow_path, &ow_path)?;
             }
             else {
                 unreachable!();
@@ -196,22 +768,185 @@ pub fn deploy(src: PathBuf, src_ent: Entity, dst: PathBuf, opt: DepOpt) -> Resul
         }
         let parent = dst.parent().unwrap();
         if !parent.is_dir() {
-            if opt.INTER {
-                fs::create_dir_all(&parent)?;
+            if !opt.INTER {
+                if opt.PLAN { plan("refuse", &Entity::DIR, parent); }
+                return Err(DeployError::locked("Deploy", "Options disallow creating intermediate directories"));
+            }
+            else if opt.PLAN {
+                // Every missing directory up to the parent is created,
+                // including one in place of any clashing file
+                let mut inter_path = ow_path;
+                if dst_ptr.entity == Entity::FILE {
+                    plan("mkdir", &Entity::DIR, &inter_path);
+                }
+                for c in dst_cmps.iter().skip(dst_ptr.ptr).take(dst_cmps.len() - dst_ptr.ptr - 1) {
+                    inter_path.push(c);
+                    plan("mkdir", &Entity::DIR, &inter_path);
+                }
             }
             else {
-                return Err(DeployError::locked("Deploy", "Options disallow creating intermediate directories"));
+                make_dirs(parent, run)?;
             }
         }
-        match &src_ent {
-            Entity::FILE => {
-                fs::copy(&src, &dst)?;
-            },
-            Entity::DIR => {
-                fs::create_dir(&dst)?;
-                copy_dir(&src, &dst)?;
+        if opt.PLAN {
+            plan("create", &src_ent, &dst);
+            plan(whence("from"), &src_ent, &src);
+            return Ok(Outcome::CREATED);
+        }
+        let staged = stage(&src, &src_ent, &dst, method, opt.PRESERVE, attrs, run)?;
+        run.create(&dst)?;
+        fs::rename(&staged, &dst)?;
+    }
+    Ok(if dst_ptr.full { Outcome::UPDATED } else { Outcome::CREATED })
+}
+
+// remove - Ensure that nothing exists at a path
+// Removals are overwrites by nothing at all, so are allowed by the flags
+// for overwriting files and directories, and backed up like any other
+pub fn remove(dst: &Path, opt: DepOpt, run: &mut state::Run) -> Result<Outcome, DeployError> {
+    let ent = match fs::symlink_metadata(dst) {
+        Ok(ref meta) if meta.is_dir() => Entity::DIR,
+        Ok(_) => Entity::FILE,
+        Err(_) => { return Ok(Outcome::UNCHANGED); },
+    };
+
+    let viable = match ent { Entity::FILE => opt.OW_FF, Entity::DIR => opt.OW_DD };
+    if !viable {
+        if opt.PLAN { plan("refuse", &ent, dst); }
+        return Err(DeployError::locked("Remove", "Options disallow this removal"));
+    }
+
+    if opt.DIFF { diff::removed(dst); }
+    if opt.PLAN {
+        plan("remove", &ent, dst);
+        return Ok(Outcome::REMOVED);
+    }
+    if opt.ASK && !run.confirm(dst, &format!("{} removed", ent.noun()))? {
+        return Ok(Outcome::SKIPPED);
+    }
+    run.backup(dst, dst)?;
+    Ok(Outcome::REMOVED)
+}
+
+// mirror - Bring a destination tree in line with its source tree in place
+// The source is merged in, so that only entities which differ are rewritten,
+// and then whatever the destination holds beyond it is removed. Entities
+// left as they were keep their inodes, and are never copied at all.
+pub fn mirror(src: PathBuf, src_ent: Entity, dst: PathBuf, opt: DepOpt, attrs: &Attrs, exclude: &glob::Exclude, run: &mut state::Run) -> Result<Outcome, DeployError> {
+    let mirroring = src_ent == Entity::DIR && dst.is_dir();
+    let outcome = deploy(src.clone(), src_ent, dst.clone(), opt, Method::MIRROR, Only::ANY, attrs, run)?;
+    if !mirroring {
+        return Ok(outcome);
+    }
+
+    let mut pruned = false;
+    for extra in extraneous(&src, &dst, exclude)? {
+        if remove(&extra, opt, run)? == Outcome::REMOVED {
+            pruned = true;
+        }
+    }
+    Ok(if pruned { Outcome::UPDATED } else { outcome })
+}
+
+// status - Compare a destination with the source it would be deployed from
+pub fn status(src: &Path, src_ent: &Entity, dst: &Path, method: Method, only: Only, keep: Preserve, attrs: &Attrs) -> Result<Drift, DeployError> {
+    // As when deploying, only a link is compared with a link, and otherwise
+    // a link to a directory stands for that directory
+    let dst_meta = if method == Method::LINK { fs::symlink_metadata(dst) } else { fs::metadata(dst).or_else(|_| fs::symlink_metadata(dst)) };
+    let dst_meta = match dst_meta {
+        Ok(meta) => meta,
+        Err(_) => { return Ok(if only == Only::UPDATE { Drift::INSYNC } else { Drift::MISSING }); },
+    };
+
+    // Once seeded, a destination is left to its own devices
+    let merging = (method == Method::MERGE || method == Method::MIRROR) && *src_ent == Entity::DIR && dst_meta.is_dir();
+    if only == Only::CREATE && !merging {
+        return Ok(Drift::INSYNC);
+    }
+
+    if method == Method::LINK {
+        return Ok(match fs::read_link(dst) {
+            Ok(ref target) if target == src && attrs.matches(dst)? => Drift::INSYNC,
+            Ok(_) => Drift::MODIFIED,
+            Err(_) => Drift::RETYPED,
+        });
+    }
+
+    if (*src_ent == Entity::DIR) != dst_meta.is_dir() {
+        return Ok(Drift::RETYPED);
+    }
+
+    // A merge only answers for the entities it carries
+    if merging {
+        for entry in walkdir::WalkDir::new(src).min_depth(1) {
+            let entry = entry?;
+            let linked = dst.join(entry.path().strip_prefix(src).unwrap());
+            let exists = fs::symlink_metadata(&linked).is_ok();
+            if (only == Only::CREATE && exists) || (only == Only::UPDATE && !exists) {
+                continue;
+            }
+            if entry.path().is_file() && !(linked.is_file() && identical(entry.path(), &linked, keep, &Attrs::default())?) {
+                return Ok(Drift::MODIFIED);
+            }
+            if entry.path().is_dir() && !linked.is_dir() {
+                return Ok(Drift::MODIFIED);
             }
         }
+        return Ok(if attrs.matches(dst)? { Drift::INSYNC } else { Drift::MODIFIED });
+    }
+
+    Ok(if identical(src, dst, keep, attrs)? && attrs.matches(dst)? { Drift::INSYNC } else { Drift::MODIFIED })
+}
+
+// mirror_status - Compare a mirrored destination with its source, which it
+// must match as a merge would and then hold nothing more besides
+pub fn mirror_status(src: &Path, src_ent: &Entity, dst: &Path, keep: Preserve, attrs: &Attrs, exclude: &glob::Exclude) -> Result<Drift, DeployError> {
+    let drift = status(src, src_ent, dst, Method::MIRROR, Only::ANY, keep, attrs)?;
+    if drift == Drift::INSYNC && *src_ent == Entity::DIR && !extraneous(src, dst, exclude)?.is_empty() {
+        return Ok(Drift::MODIFIED);
+    }
+    Ok(drift)
+}
+
+// capture - Deploy a live destination back over its bundle source
+// Links already lead into the bundle, so there is nothing to capture from them,
+// and merges only bring back the entities that they carried out
+// Excluded entities are never captured, so a directory with exclusions is
+// brought back as a merge would be, leaving whatever is excluded alone
+// Templates are likewise left alone, since their renderings would overwrite them
+// Nor is a destination only ever created, which is the host's own once it exists
+pub fn capture(src: PathBuf, dst: PathBuf, opt: DepOpt, method: Method, only: Only, exclude: &glob::Exclude, run: &mut state::Run) -> Result<Outcome, DeployError> {
+    let live_ent = match fs::symlink_metadata(&dst) {
+        Ok(_) if dst.is_dir() => Entity::DIR,
+        Ok(_) => Entity::FILE,
+        Err(_) => { return Ok(Outcome::SKIPPED); },
+    };
+    if only == Only::CREATE {
+        return Ok(Outcome::CREATE_ONLY);
+    }
+    let partial = !exclude.is_empty() || template::contains(&src);
+    let method = if method == Method::REPLACE && partial && src.is_dir() { Method::MERGE } else { method };
+
+    match method {
+        Method::LINK => Ok(Outcome::UNCHANGED),
+        Method::MERGE | Method::MIRROR if live_ent == Entity::DIR && src.is_dir() => {
+            let mut outcome = Outcome::UNCHANGED;
+            let walk = walkdir::WalkDir::new(&src).min_depth(1).into_iter().filter_entry(|e| {
+                !exclude.excludes(e.path().strip_prefix(&src).unwrap(), e.path().is_dir())
+            });
+            for entry in walk {
+                let entry = entry?;
+                let linked = dst.join(entry.path().strip_prefix(&src).unwrap());
+                if !entry.path().is_file() || !linked.is_file() || template::is_template(entry.path()) {
+                    continue;
+                }
+                let sub_outcome = deploy(linked, Entity::FILE, entry.path().to_path_buf(), opt, Method::REPLACE, Only::ANY, &Attrs::default(), run)?;
+                if sub_outcome == Outcome::UPDATED {
+                    outcome = Outcome::UPDATED;
+                }
+            }
+            Ok(outcome)
+        },
+        _ => deploy(dst, live_ent, src, opt, Method::REPLACE, Only::ANY, &Attrs::default(), run),
     }
-    Ok(())
 }
@@ -0,0 +1,321 @@
+use std::fs;
+use std::path::Path;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+
+// Lines of context shown around each change
+const CONTEXT: usize = 3;
+
+// Beyond this many lines, files are summarised rather than diffed
+const MAX_LINES: usize = 20_000;
+
+// Beyond this many differing lines, likewise, which bounds the memory
+// the search for them may take
+const MAX_EDITS: isize = 2_000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Edit {
+    KEEP,
+    DEL,
+    INS,
+}
+
+// edits - Find a shortest edit script between two sequences of lines,
+// using Myers' O(ND) algorithm, if there is one no longer than MAX_EDITS
+fn edits(a: &[&str], b: &[&str]) -> Option<Vec<Edit>> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    let idx = |k: isize| (k + max + 1) as usize;
+
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = vec![];
+
+    // Each round only reads the diagonals within d + 1 of the middle,
+    // so only those are kept for the walk back
+    'search: for d in 0..(max + 1) {
+        if d > MAX_EDITS {
+            return None;
+        }
+        trace.push(v[idx(-d - 1)..idx(d + 1) + 1].to_vec());
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    // Walk back through the trace to recover the path we took
+    let mut script = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            script.push(Edit::KEEP);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                script.push(Edit::INS);
+                y -= 1;
+            }
+            else {
+                script.push(Edit::DEL);
+                x -= 1;
+            }
+        }
+    }
+    script.reverse();
+    Some(script)
+}
+
+// is_binary - Guess whether some content is binary, as diff(1) does
+fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|b| *b == 0) || ::std::str::from_utf8(data).is_err()
+}
+
+// unified - Write a unified diff of two texts, unless they are too long or
+// too different to be worth showing
+fn unified(old_label: &Path, new_label: &Path, old: &str, new: &str) -> Option<String> {
+    if old.lines().count() + new.lines().count() > MAX_LINES {
+        return None;
+    }
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let script = edits(&a, &b)?;
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label.display(), new_label.display());
+
+    // Pair each edit with its position in both files
+    let mut steps = vec![];
+    let (mut x, mut y) = (0, 0);
+    for edit in script {
+        steps.push((edit, x, y));
+        match edit {
+            Edit::KEEP => { x += 1; y += 1; },
+            Edit::DEL  => { x += 1; },
+            Edit::INS  => { y += 1; },
+        }
+    }
+
+    // Gather changes into hunks, joining those whose context would overlap
+    let mut i = 0;
+    while i < steps.len() {
+        if steps[i].0 == Edit::KEEP {
+            i += 1;
+            continue;
+        }
+        let start = i.saturating_sub(CONTEXT);
+        let mut end = i;
+        let mut j = i;
+        while j < steps.len() {
+            if steps[j].0 != Edit::KEEP {
+                end = j;
+            }
+            else if j - end > 2 * CONTEXT {
+                break;
+            }
+            j += 1;
+        }
+        let end = (end + CONTEXT + 1).min(steps.len());
+        let hunk = &steps[start..end];
+
+        let old_len = hunk.iter().filter(|s| s.0 != Edit::INS).count();
+        let new_len = hunk.iter().filter(|s| s.0 != Edit::DEL).count();
+        let old_start = if old_len == 0 { hunk[0].1 } else { hunk[0].1 + 1 };
+        let new_start = if new_len == 0 { hunk[0].2 } else { hunk[0].2 + 1 };
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_len, new_start, new_len));
+        for &(edit, x, y) in hunk {
+            let (mark, line) = match edit {
+                Edit::KEEP => (' ', a[x]),
+                Edit::DEL  => ('-', a[x]),
+                Edit::INS  => ('+', b[y]),
+            };
+            out.push(mark);
+            out.push_str(line);
+            out.push('\n');
+        }
+        i = end;
+    }
+    Some(out)
+}
+
+fn show_files(old: &Path, new: &Path) {
+    let (old_data, new_data) = match (fs::read(old), fs::read(new)) {
+        (Ok(o), Ok(n)) => (o, n),
+        _ => {
+            println!("Could not read {} or {}", old.display(), new.display());
+            return;
+        },
+    };
+    if old_data == new_data {
+        return;
+    }
+    if is_binary(&old_data) || is_binary(&new_data) {
+        println!("Binary files {} and {} differ ({} -> {} bytes)",
+                 old.display(), new.display(), old_data.len(), new_data.len());
+        return;
+    }
+
+    let old_text = String::from_utf8_lossy(&old_data);
+    let new_text = String::from_utf8_lossy(&new_data);
+    match unified(old, new, &old_text, &new_text) {
+        Some(text) => print!("{}", text),
+        None => println!("Files {} and {} differ (too long to show)", old.display(), new.display()),
+    }
+}
+
+fn list(dir: &Path) -> Vec<OsString> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.file_name()).collect(),
+        Err(_) => vec![],
+    }
+}
+
+// show_text - Print the differences a change to the text of a file would make
+pub fn show_text(path: &Path, old: &str, new: &str) {
+    match unified(path, path, old, new) {
+        Some(text) => print!("{}", text),
+        None => println!("File {} would change (too long to show)", path.display()),
+    }
+}
+
+// removed - Note an entity that is to be removed outright
+pub fn removed(old: &Path) {
+    println!("--- {} (removed)", old.display());
+}
+
+// show - Print the differences between a live entity and its replacement
+pub fn show(old: &Path, new: &Path) {
+    let old_meta = fs::metadata(old).ok();
+    let new_meta = fs::metadata(new).ok();
+    match (old_meta, new_meta) {
+        (None, Some(_)) => println!("+++ {} (new)", new.display()),
+        (Some(_), None) => removed(old),
+        (Some(ref o), Some(ref n)) if o.is_dir() && n.is_dir() => {
+            let names: BTreeSet<OsString> = list(old).into_iter().chain(list(new)).collect();
+            for name in names {
+                show(&old.join(&name), &new.join(&name));
+            }
+        },
+        (Some(ref o), Some(ref n)) if o.is_dir() != n.is_dir() => {
+            let kind = |dir: bool| if dir { "directory" } else { "file" };
+            println!("*** {} ({}) replaced by {} ({})",
+                     old.display(), kind(o.is_dir()), new.display(), kind(n.is_dir()));
+        },
+        (Some(_), Some(_)) => show_files(old, new),
+        (None, None) => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // apply - Replay an edit script over the old lines, to check it yields the new
+    fn apply<'a>(a: &[&'a str], b: &[&'a str], script: &[Edit]) -> Vec<&'a str> {
+        let (mut x, mut y) = (0, 0);
+        let mut out = vec![];
+        for edit in script {
+            match *edit {
+                Edit::KEEP => { out.push(a[x]); x += 1; y += 1; },
+                Edit::DEL  => { x += 1; },
+                Edit::INS  => { out.push(b[y]); y += 1; },
+            }
+        }
+        assert_eq!((x, y), (a.len(), b.len()));
+        out
+    }
+
+    fn diff(old: &str, new: &str) -> String {
+        unified(Path::new("a"), Path::new("b"), old, new).unwrap()
+    }
+
+    #[test]
+    fn edits_of_empty_sequences() {
+        assert_eq!(edits(&[], &[]), Some(vec![]));
+        assert_eq!(edits(&[], &["x"]), Some(vec![Edit::INS]));
+        assert_eq!(edits(&["x"], &[]), Some(vec![Edit::DEL]));
+    }
+
+    #[test]
+    fn edits_keep_identical_lines() {
+        assert_eq!(edits(&["x", "y"], &["x", "y"]), Some(vec![Edit::KEEP, Edit::KEEP]));
+    }
+
+    #[test]
+    fn edits_are_shortest_and_replay() {
+        let a = ["a", "b", "c", "a", "b", "b", "a"];
+        let b = ["c", "b", "a", "b", "a", "c"];
+        let script = edits(&a, &b).unwrap();
+        assert_eq!(apply(&a, &b, &script), b.to_vec());
+        // The classic example from Myers' paper has an edit distance of 5
+        assert_eq!(script.iter().filter(|e| **e != Edit::KEEP).count(), 5);
+    }
+
+    #[test]
+    fn edits_give_up_beyond_the_limit() {
+        let a: Vec<String> = (0..MAX_EDITS + 1).map(|i| format!("a{}", i)).collect();
+        let b: Vec<String> = (0..MAX_EDITS + 1).map(|i| format!("b{}", i)).collect();
+        let a: Vec<&str> = a.iter().map(|s| s.as_str()).collect();
+        let b: Vec<&str> = b.iter().map(|s| s.as_str()).collect();
+        assert_eq!(edits(&a, &b), None);
+    }
+
+    #[test]
+    fn unified_from_empty_file() {
+        assert_eq!(diff("", "x\n"), "--- a\n+++ b\n@@ -0,0 +1,1 @@\n+x\n");
+    }
+
+    #[test]
+    fn unified_to_empty_file() {
+        assert_eq!(diff("x\n", ""), "--- a\n+++ b\n@@ -1,1 +0,0 @@\n-x\n");
+    }
+
+    #[test]
+    fn unified_shows_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        assert_eq!(diff(old, new), "--- a\n+++ b\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n");
+    }
+
+    // lines - Number lines from one up to n, renaming some of them
+    fn lines(n: usize, renamed: &[usize]) -> String {
+        (1..n + 1).map(|i| if renamed.contains(&i) { format!("new{}\n", i) } else { format!("{}\n", i) }).collect()
+    }
+
+    #[test]
+    fn unified_joins_nearby_hunks() {
+        // Six unchanged lines between changes is context enough for both
+        let out = diff(&lines(19, &[]), &lines(19, &[3, 10]));
+        assert_eq!(out.matches("@@ -").count(), 1);
+        assert!(out.contains("@@ -1,13 +1,13 @@\n"));
+    }
+
+    #[test]
+    fn unified_splits_distant_hunks() {
+        let out = diff(&lines(29, &[]), &lines(29, &[2, 20]));
+        assert_eq!(out.matches("@@ -").count(), 2);
+        assert!(out.contains("@@ -1,5 +1,5 @@\n"));
+        assert!(out.contains("@@ -17,7 +17,7 @@\n"));
+    }
+}
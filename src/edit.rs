@@ -0,0 +1,312 @@
+extern crate regex;
+
+use std::fs;
+use std::os::unix::fs as unix_fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use deploy::{self, DeployError, DepOpt, Drift, Entity, Outcome};
+use diff;
+use state;
+
+// Edits change part of a file in place, for system files such as /etc/hosts
+// which a bundle cannot own outright. A file is only written when applying
+// its edit would change it, so that running an edit again does nothing.
+// Edited files are never noted for the manifest, since they are not the
+// bundle's to prune.
+
+pub enum Edit {
+    PRESENT(String),             // A line is present, appended if need be
+    ABSENT(String),              // A line is absent, wherever it was
+    SUBST(regex::Regex, String), // Each match of a pattern is replaced
+    SET {                        // A key has a value, within a section if given
+        key: String,
+        value: String,
+        sep: String,
+        section: Option<String>,
+    },
+}
+
+// is_header - Determine whether a line opens a section, as in [section]
+fn is_header(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with('[') && line.ends_with(']')
+}
+
+// is_key - Determine whether a line sets a key, with a separator that
+// is either whitespace or, say, an '=' padded by any amount of whitespace
+fn is_key(line: &str, key: &str, sep: &str) -> bool {
+    let line = line.trim_start();
+    if line.starts_with('#') || line.starts_with(';') {
+        return false;
+    }
+    match line.strip_prefix(key) {
+        Some(rest) if sep.trim().is_empty() => rest.starts_with(char::is_whitespace),
+        Some(rest) => rest.trim_start().starts_with(sep.trim()),
+        None => false,
+    }
+}
+
+impl Edit {
+    // creates - Determine whether this edit would create a missing file
+    fn creates(&self) -> bool {
+        match *self {
+            Edit::PRESENT(_) | Edit::SET { .. } => true,
+            Edit::ABSENT(_) | Edit::SUBST(..) => false,
+        }
+    }
+
+    // apply - Find the text of a file once edited
+    pub fn apply(&self, old: &str) -> String {
+        let old_lines: Vec<&str> = old.lines().collect();
+        let mut lines: Vec<String> = old_lines.iter().map(|l| l.to_string()).collect();
+
+        match *self {
+            Edit::PRESENT(ref line) => {
+                if !lines.contains(line) {
+                    lines.push(line.clone());
+                }
+            },
+            Edit::ABSENT(ref line) => {
+                lines.retain(|l| l != line);
+            },
+            Edit::SUBST(ref pattern, ref replacement) => {
+                for l in lines.iter_mut() {
+                    *l = pattern.replace_all(l, replacement.as_str()).to_string();
+                }
+            },
+            Edit::SET { ref key, ref value, ref sep, ref section } => {
+                let setting = format!("{}{}{}", key, sep, value);
+
+                // Keys outside of any section come before the first one
+                let (start, end) = match *section {
+                    None => (0, lines.iter().position(|l| is_header(l)).unwrap_or(lines.len())),
+                    Some(ref section) => {
+                        let header = format!("[{}]", section);
+                        match lines.iter().position(|l| l.trim() == header) {
+                            Some(at) => {
+                                let end = lines.iter().skip(at + 1).position(|l| is_header(l))
+                                                                   .map(|n| at + 1 + n)
+                                                                   .unwrap_or(lines.len());
+                                (at + 1, end)
+                            },
+                            None => {
+                                if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                                    lines.push(String::new());
+                                }
+                                lines.push(header);
+                                (lines.len(), lines.len())
+                            },
+                        }
+                    },
+                };
+
+                match (start..end).find(|i| is_key(&lines[*i], key, sep)) {
+                    Some(i) => {
+                        // Keep the indentation of the line being replaced
+                        let indent = lines[i].len() - lines[i].trim_start().len();
+                        lines[i] = format!("{}{}", &lines[i][..indent], setting);
+                    },
+                    None => {
+                        let at = (start..end).rev().find(|i| !lines[*i].trim().is_empty())
+                                             .map(|i| i + 1)
+                                             .unwrap_or(start);
+                        lines.insert(at, setting);
+                    },
+                }
+            },
+        }
+
+        // Unchanged files are left exactly as they were, final newline or not
+        if lines.iter().map(|l| l.as_str()).eq(old_lines.iter().cloned()) {
+            return old.to_string();
+        }
+        let mut new = lines.join("\n");
+        new.push('\n');
+        new
+    }
+}
+
+// resolve - Find the file that an edit actually applies to
+// A link is followed to the file it leads to, which is edited in its place,
+// so that the link itself is never replaced with a plain file
+fn resolve(path: &Path) -> Result<PathBuf, DeployError> {
+    match fs::symlink_metadata(path) {
+        Ok(ref meta) if meta.file_type().is_symlink() => fs::canonicalize(path).map_err(|_| {
+            DeployError { source: "Edit".to_owned(),
+                          message: format!("Link {:?} leads nowhere", path) }
+        }),
+        _ => Ok(path.to_path_buf()),
+    }
+}
+
+// read - Read the text of a file to be edited, if it exists
+fn read(path: &Path) -> Result<Option<String>, DeployError> {
+    match fs::symlink_metadata(path) {
+        Err(_) => Ok(None),
+        Ok(ref meta) if !meta.is_file() => {
+            Err(DeployError::locked("Edit", "Only files can be edited"))
+        },
+        Ok(_) => {
+            let text = fs::read_to_string(path).map_err(|_| {
+                DeployError { source: "Edit".to_owned(),
+                              message: format!("Could not read {:?} as text", path) }
+            })?;
+            Ok(Some(text))
+        },
+    }
+}
+
+// edit - Apply an edit to a file, planned, shown and confirmed as a deployment is
+pub fn edit(path: &Path, edit: &Edit, opt: DepOpt, run: &mut state::Run) -> Result<Outcome, DeployError> {
+    let path = &resolve(path)?;
+    let old = read(path)?;
+    if old.is_none() && !edit.creates() {
+        return Ok(Outcome::UNCHANGED);
+    }
+
+    let old_text = old.clone().unwrap_or_default();
+    let new_text = edit.apply(&old_text);
+    if old.is_some() && new_text == old_text {
+        if opt.PLAN { deploy::plan("keep", &Entity::FILE, path); }
+        return Ok(Outcome::UNCHANGED);
+    }
+
+    if opt.DIFF { diff::show_text(path, &old_text, &new_text); }
+    if opt.PLAN {
+        deploy::plan(if old.is_some() { "edit" } else { "create" }, &Entity::FILE, path);
+        return Ok(if old.is_some() { Outcome::UPDATED } else { Outcome::CREATED });
+    }
+    if old.is_some() && opt.ASK && !run.confirm(path, "edited file")? {
+        return Ok(Outcome::SKIPPED);
+    }
+    if !path.parent().is_some_and(|p| p.is_dir()) {
+        return Err(DeployError::locked("Edit", "No directory to create this file in"));
+    }
+
+    // The edited file is staged beside the original and swapped in, with
+    // the original's mode and ownership, so that it can be rolled back
+    let staged = deploy::sibling(path, "jann-stage");
+    if fs::symlink_metadata(&staged).is_ok() {
+        fs::remove_file(&staged)?;
+    }
+    run.create(&staged)?;
+    fs::write(&staged, new_text)?;
+    if old.is_none() {
+        run.create(path)?;
+        fs::rename(&staged, path)?;
+        return Ok(Outcome::CREATED);
+    }
+
+    let meta = fs::metadata(path)?;
+    let staged_meta = fs::metadata(&staged)?;
+    if meta.uid() != staged_meta.uid() || meta.gid() != staged_meta.gid() {
+        unix_fs::lchown(&staged, Some(meta.uid()), Some(meta.gid()))?;
+    }
+    fs::set_permissions(&staged, meta.permissions())?;
+    run.swap(&staged, path)?;
+    run.backup(&staged, path)?;
+    Ok(Outcome::UPDATED)
+}
+
+// status - Determine whether a file already has an edit applied
+pub fn status(path: &Path, edit: &Edit) -> Result<Drift, DeployError> {
+    let path = &resolve(path)?;
+    if fs::symlink_metadata(path).is_ok_and(|m| !m.is_file()) {
+        return Ok(Drift::RETYPED);
+    }
+    Ok(match read(path)? {
+        None => if edit.creates() { Drift::MISSING } else { Drift::INSYNC },
+        Some(text) => if edit.apply(&text) == text { Drift::INSYNC } else { Drift::MODIFIED },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(key: &str, value: &str, sep: &str, section: Option<&str>) -> Edit {
+        Edit::SET { key: key.to_string(), value: value.to_string(), sep: sep.to_string(),
+                    section: section.map(String::from) }
+    }
+
+    // applied - Apply an edit, checking that applying it again changes nothing
+    fn applied(edit: &Edit, old: &str) -> String {
+        let new = edit.apply(old);
+        assert_eq!(edit.apply(&new), new);
+        new
+    }
+
+    #[test]
+    fn present_appends_once() {
+        let edit = Edit::PRESENT("10.0.0.5 server".to_string());
+        assert_eq!(applied(&edit, "127.0.0.1 localhost\n"), "127.0.0.1 localhost\n10.0.0.5 server\n");
+        assert_eq!(applied(&edit, ""), "10.0.0.5 server\n");
+    }
+
+    #[test]
+    fn unchanged_text_keeps_its_final_newline_or_lack_of_one() {
+        let edit = Edit::PRESENT("a".to_string());
+        assert_eq!(edit.apply("a"), "a");
+        assert_eq!(edit.apply("a\r\nb"), "a\r\nb");
+    }
+
+    #[test]
+    fn absent_removes_every_copy() {
+        let edit = Edit::ABSENT("x".to_string());
+        assert_eq!(applied(&edit, "x\ny\nx\n"), "y\n");
+        assert_eq!(applied(&edit, "y"), "y");
+    }
+
+    #[test]
+    fn subst_replaces_with_groups() {
+        let edit = Edit::SUBST(regex::Regex::new("^#?PasswordAuthentication .*").unwrap(),
+                               "PasswordAuthentication no".to_string());
+        assert_eq!(applied(&edit, "#PasswordAuthentication yes\nPort 22\n"), "PasswordAuthentication no\nPort 22\n");
+        let edit = Edit::SUBST(regex::Regex::new("(\\w+)@old").unwrap(), "$1@new".to_string());
+        assert_eq!(edit.apply("me@old\n"), "me@new\n");
+    }
+
+    #[test]
+    fn set_replaces_a_key_in_place() {
+        let edit = set("a", "2", " = ", None);
+        assert_eq!(applied(&edit, "a = 1\nb = 2\n"), "a = 2\nb = 2\n");
+        // The separator matches however it is padded, and indentation is kept
+        assert_eq!(applied(&edit, "  a=1\n"), "  a = 2\n");
+    }
+
+    #[test]
+    fn set_ignores_comments_and_longer_keys() {
+        let edit = set("a", "2", " = ", None);
+        assert_eq!(applied(&edit, "# a = 1\nab = 1\n"), "# a = 1\nab = 1\na = 2\n");
+    }
+
+    #[test]
+    fn set_with_whitespace_separator() {
+        let edit = set("PermitRootLogin", "no", " ", None);
+        assert_eq!(applied(&edit, "PermitRootLogin yes\n"), "PermitRootLogin no\n");
+        assert_eq!(applied(&edit, "PermitRootLoginX yes\n"), "PermitRootLoginX yes\nPermitRootLogin no\n");
+    }
+
+    #[test]
+    fn set_without_section_goes_before_the_first() {
+        let edit = set("a", "1", " = ", None);
+        assert_eq!(applied(&edit, "x = 0\n\n[core]\na = 0\n"), "x = 0\na = 1\n\n[core]\na = 0\n");
+    }
+
+    #[test]
+    fn set_into_an_existing_section() {
+        let edit = set("editor", "nvim", " = ", Some("core"));
+        let old = "[core]\n\teditor = vi\n[user]\n\teditor = x\n";
+        assert_eq!(applied(&edit, old), "[core]\n\teditor = nvim\n[user]\n\teditor = x\n");
+        let old = "[core]\nbare = false\n\n[user]\nname = me\n";
+        assert_eq!(applied(&edit, old), "[core]\nbare = false\neditor = nvim\n\n[user]\nname = me\n");
+    }
+
+    #[test]
+    fn set_into_a_missing_section() {
+        let edit = set("editor", "nvim", " = ", Some("core"));
+        assert_eq!(applied(&edit, "[user]\nname = me\n"), "[user]\nname = me\n\n[core]\neditor = nvim\n");
+        assert_eq!(applied(&edit, ""), "[core]\neditor = nvim\n");
+    }
+}
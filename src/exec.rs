@@ -1,5 +1,6 @@
 extern crate walkdir;
 extern crate dirs;
+extern crate regex;
 
 use parse::PTNodeType;
 use std::process::Command;
@@ -12,6 +13,11 @@ use invoke;
 use inter;
 use util;
 use parse;
+use state;
+use template;
+use glob;
+use archive;
+use edit;
 
 fn component_string(c: &Component) -> String {
     c.as_os_str().to_string_lossy().to_string()
@@ -31,6 +37,30 @@ fn command<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
     };
 
     let outcom = inter::interpolate(log, symbols, node.token_value(), node);
+
+    // Status checks and captures leave commands alone entirely
+    if inv.opts.STATUS || inv.opts.CAPTURE {
+        return;
+    }
+
+    // When archiving we record the command alongside what it would have run on
+    if inv.opts.ARCHIVE {
+        if let Some(ref stage) = inv.target {
+            let cwd = env::current_dir().unwrap_or_default();
+            if let Err(err) = archive::record(stage, &cwd, &outcom) {
+                log.terminal(&format!("Archive error: [{}] {}", &err.source, &err.message),
+                             "Modify this line appropriately", &node.tok);
+            }
+        }
+        return;
+    }
+
+    // When planning we report the command we would have run
+    if inv.opts.PLAN {
+        println!("[   Plan] $ {}", outcom);
+        return;
+    }
+
     println!(">>> {}", outcom);
     
     let mut proc = Command::new(&shell)
@@ -42,9 +72,400 @@ fn command<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
     if !proc.wait().expect("failed to wait on process").success() { println!("Command ended with non-zero status") }
 }
 
+// destination - Resolve the destination path of a statement
+fn destination<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
+                                 symbols: &inter::Symbols<'src>,
+                                 log: &mut util::Log<'src>,
+                                 node: &inter::LinkNode<'inv, 'src>) -> PathBuf {
+    let mut dst_buf = PathBuf::from(inter::interpolate(log, symbols, &node.token_value(), node));
+
+    let dst_cpy = dst_buf.clone();
+    let dst_comps: Vec<Component> = dst_cpy.components().collect();
+
+    if dst_comps.len() == 0 {
+        log.terminal("Destination path is empty (this should not be allowed by the parser)",
+                     "Put a path here and then please file a bug report!",
+                     &node.tok);
+
+    }
+
+    dst_buf = if let Ok(dst_tail) = dst_buf.strip_prefix("~") {
+        dirs::home_dir().unwrap_or_else( || {
+            log.sys_terminal("Could not find home directory");
+        }).join(dst_tail)
+    }
+    else {
+        dst_buf
+    };
+
+    if !dst_buf.components().all(|c| match c {
+        Component::CurDir | Component::ParentDir => false,
+        _ => true,
+    }) {
+        log.terminal(&format!("Invalid destination path {:?}", dst_buf),
+                     "Ensure path is absolute",
+                     &node.tok);
+    }
+
+    rooted(inv, dst_buf)
+}
+
+// rooted - Move an absolute path under the target root, when there is one
+fn rooted(inv: &invoke::Invocation, path: PathBuf) -> PathBuf {
+    match inv.target {
+        Some(ref target) => target.join(path.strip_prefix("/").unwrap_or(&path)),
+        None => path,
+    }
+}
+
+// ignores - Load the patterns of the bundle's .jannignore, relative to base
+fn ignores(inv: &invoke::Invocation, base: &Path, log: &mut util::Log) -> glob::Exclude {
+    let mut exclude = glob::Exclude::new();
+    let ignore = inv.root.join(".jannignore");
+    if let Ok(patterns) = fs::read_to_string(&ignore) {
+        for (i, line) in patterns.lines().enumerate() {
+            if let Err(err) = exclude.add(base, line) {
+                log.file_terminal("Invalid exclude pattern", &err, &ignore, i + 1, line);
+            }
+        }
+    }
+    exclude
+}
+
+// attribute - Apply a mode, owner or group modifier to a destination's attributes
+fn attribute<'inv, 'src: 'inv>(log: &mut util::Log<'src>,
+                               attrs: &mut deploy::Attrs,
+                               key: &str,
+                               val: inter::Value,
+                               node: &inter::LinkNode<'inv, 'src>) {
+    match key {
+        "mode" => {
+            match val {
+                inter::Value::Str(ref v) => match u32::from_str_radix(v, 8) {
+                    Ok(mode) if mode <= 0o7777 => { attrs.mode = Some(mode); },
+                    _ => {
+                        log.terminal("Invalid mode", "Give the mode in octal, such as 0644", node.tok);
+                    },
+                },
+                _ => { log.terminal("Modes must be strings", "Give the mode in octal, such as 0644", node.tok); },
+            }
+        },
+        "owner" => {
+            match val {
+                inter::Value::Str(ref v) => match deploy::user(v) {
+                    Some(uid) => { attrs.uid = Some(uid); },
+                    None => { log.terminal(&format!("No such user {}", v), "Name an extant user", node.tok); },
+                },
+                _ => { log.terminal("Owners must be strings", "Name a user", node.tok); },
+            }
+        },
+        "group" => {
+            match val {
+                inter::Value::Str(ref v) => match deploy::group(v) {
+                    Some(gid) => { attrs.gid = Some(gid); },
+                    None => { log.terminal(&format!("No such group {}", v), "Name an extant group", node.tok); },
+                },
+                _ => { log.terminal("Groups must be strings", "Name a group", node.tok); },
+            }
+        },
+        _ => unreachable!(),
+    }
+}
+
+// modifiers - Apply the modifiers that any destination may take, its mode,
+// owner and group, and whether it is only created or only updated
+// Whatever else a statement is modified by is handed back to it
+fn modifiers<'inv, 'src: 'inv>(symbols: &inter::Symbols<'src>,
+                               log: &mut util::Log<'src>,
+                               mods: &inter::LinkNode<'inv, 'src>,
+                               attrs: &mut deploy::Attrs,
+                               only: &mut deploy::Only) -> Vec<inter::LinkNode<'inv, 'src>> {
+    let mut rest = vec![];
+    for m in mods.children() {
+        if m.is_type(&PTNodeType::ASSIGN) {
+            let m_children = m.children();
+            match m_children[0].token_value() {
+                key @ "mode" | key @ "owner" | key @ "group" => {
+                    let val = inter::load_value(symbols, &m_children[1]);
+                    attribute(log, attrs, key, val, &m_children[1]);
+                },
+                _ => rest.push(m),
+            }
+            continue;
+        }
+        if !m.is_type(&PTNodeType::NAME) {
+            log.terminal("Invalid modifier", "Make this a name", m.tok);
+        }
+        match m.token_value() {
+            "create_only" | "update_only" if *only != deploy::Only::ANY => {
+                log.terminal("Conflicting modifiers", "Keep only one of 'create_only' and 'update_only'", m.tok);
+            },
+            "create_only" => { *only = deploy::Only::CREATE; },
+            "update_only" => { *only = deploy::Only::UPDATE; },
+            _ => rest.push(m),
+        }
+    }
+    rest
+}
+
+// fail - Report an error from carrying out a statement
+// A plan carries on past it, so that it reports the whole run
+fn fail<'src>(inv: &invoke::Invocation<'src>,
+              log: &mut util::Log<'src>,
+              what: &str,
+              err: &deploy::DeployError,
+              tok: &parse::Token) {
+    let msg = format!("{} error: [{}] {}", what, &err.source, &err.message);
+    if inv.opts.PLAN {
+        log.error(&msg, "Modify this line appropriately", tok);
+    }
+    else {
+        log.terminal(&msg, "Modify this line appropriately", tok);
+    }
+}
+
+// edit_file - Execute a statement editing part of a file in place
+fn edit_file<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
+                               symbols: &mut inter::Symbols<'src>,
+                               log: &mut util::Log<'src>,
+                               run: &mut state::Run,
+                               node: &inter::LinkNode<'inv, 'src>) {
+    let edit_children = &node.children();
+    let path = destination(inv, symbols, log, &edit_children[0]);
+    if !path.is_absolute() {
+        log.terminal("Edited files must be given by absolute path", "Make this path absolute", &edit_children[0].tok);
+    }
+
+    // Every part of the value is interpolated, as a command would be
+    let value = &edit_children[1];
+    let parts: Vec<String> = match inter::load_value(symbols, value) {
+        inter::Value::Str(ref s) => vec![inter::interpolate(log, symbols, s, value)],
+        inter::Value::List(vals) => vals.iter().map(|v| match *v {
+            inter::Value::Str(ref s) => inter::interpolate(log, symbols, s, value),
+            _ => { log.terminal("Edits take only strings", "Make each of these a string", value.tok); },
+        }).collect(),
+        _ => { log.terminal("Edits take only strings", "Make this a string", value.tok); },
+    };
+
+    let mut sep = String::from(" = ");
+    let mut section = None;
+    if let Some(mods) = edit_children.get(2) {
+        for m in mods.children() {
+            let m_children = m.children();
+            let key = if m.is_type(&PTNodeType::ASSIGN) { &m_children[0] } else { &m };
+            if !node.is_type(&PTNodeType::SETKEY) {
+                log.terminal("Unknown modifier", "Only key settings take modifiers", key.tok);
+            }
+            let val = match m_children.get(1).map(|v| inter::load_value(symbols, v)) {
+                Some(inter::Value::Str(v)) => v,
+                _ => { log.terminal("Invalid modifier", "Give this a string value", key.tok); },
+            };
+            match key.token_value() {
+                "sep" => { sep = val; },
+                "section" => { section = Some(val); },
+                _ => { log.terminal("Unknown modifier", "Use a valid modifier such as 'sep' or 'section'", key.tok); },
+            }
+        }
+    }
+
+    let edit = match (&node.ptn.nt, parts.as_slice()) {
+        (&PTNodeType::PRESENT, [line]) => edit::Edit::PRESENT(line.clone()),
+        (&PTNodeType::ABSENT, [line]) => edit::Edit::ABSENT(line.clone()),
+        (&PTNodeType::SUBST, [pattern, replacement]) => {
+            match regex::Regex::new(pattern) {
+                Ok(pattern) => edit::Edit::SUBST(pattern, replacement.clone()),
+                Err(err) => { log.terminal(&format!("Invalid pattern: {}", err), "Fix this pattern", value.tok); },
+            }
+        },
+        (&PTNodeType::SETKEY, [key, val]) => {
+            edit::Edit::SET { key: key.clone(), value: val.clone(), sep, section }
+        },
+        (&PTNodeType::SUBST, _) => { log.terminal("Expected a pattern and replacement", "Make this [pattern, replacement]", value.tok); },
+        (&PTNodeType::SETKEY, _) => { log.terminal("Expected a key and value", "Make this [key, value]", value.tok); },
+        _ => { log.terminal("Expected a single line", "Make this a string", value.tok); },
+    };
+
+    // An archive holds whole files, so a machine unpacking it would go
+    // without the edit, which is refused rather than quietly dropped
+    if inv.opts.ARCHIVE {
+        log.terminal("Edits cannot be archived",
+                     "Make this edit with a command instead, or disable its block when archiving", &node.tok);
+    }
+
+    // Captures bring whole files back, so cannot carry an edit
+    if inv.opts.CAPTURE {
+        log.report(deploy::Outcome::SKIPPED.name(), &path);
+        return;
+    }
+
+    if inv.opts.STATUS {
+        match edit::status(&path, &edit) {
+            Ok(drift) => {
+                if drift != deploy::Drift::INSYNC {
+                    run.drifted = true;
+                }
+                log.report(drift.name(), &path);
+            },
+            Err(result) => {
+                log.terminal(&format!("Status error: [{}] {}", &result.source, &result.message),
+                             "Modify this line appropriately", &node.tok);
+            },
+        }
+        return;
+    }
+
+    match edit::edit(&path, &edit, inv.opts, run) {
+        Ok(outcome) => {
+            if !inv.opts.PLAN {
+                log.report(outcome.name(), &path);
+            }
+        },
+        Err(result) => {
+            fail(inv, log, "Edit", &result, node.tok);
+        },
+    }
+}
+
+// remove - Execute a statement ensuring that nothing exists at a path
+fn remove<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
+                            symbols: &mut inter::Symbols<'src>,
+                            log: &mut util::Log<'src>,
+                            run: &mut state::Run,
+                            node: &inter::LinkNode<'inv, 'src>) {
+    let target = &node.children()[0];
+    let path = destination(inv, symbols, log, target);
+    if !path.is_absolute() {
+        log.terminal("Removed paths must be absolute", "Make this path absolute", &target.tok);
+    }
+    // Home is guarded wherever it has been moved to
+    let home = dirs::home_dir().map(|home| rooted(inv, home));
+    if path.parent().is_none() || Some(&path) == inv.target.as_ref() || Some(&path) == home.as_ref() {
+        log.terminal(&format!("Refusing to remove {:?}", path), "Name something within this directory", &target.tok);
+    }
+
+    // An archive cannot hold an absence, so it carries the command instead
+    if inv.opts.ARCHIVE {
+        if let Some(ref stage) = inv.target {
+            let real = Path::new("/").join(path.strip_prefix(stage).unwrap_or(&path));
+            let cwd = env::current_dir().unwrap_or_default();
+            if let Err(err) = archive::record(stage, &cwd, &format!("rm -rf {}", archive::quote(&real))) {
+                log.terminal(&format!("Archive error: [{}] {}", &err.source, &err.message),
+                             "Modify this line appropriately", &node.tok);
+            }
+        }
+        return;
+    }
+
+    if inv.opts.CAPTURE {
+        return;
+    }
+
+    if inv.opts.STATUS {
+        if fs::symlink_metadata(&path).is_ok() {
+            run.drifted = true;
+            log.report(deploy::Drift::PRESENT.name(), &path);
+        }
+        else {
+            log.report(deploy::Drift::INSYNC.name(), &path);
+        }
+        return;
+    }
+
+    match deploy::remove(&path, inv.opts, run) {
+        Ok(outcome) => {
+            if !inv.opts.PLAN {
+                log.report(outcome.name(), &path);
+            }
+        },
+        Err(result) => {
+            fail(inv, log, "Removal", &result, node.tok);
+        },
+    }
+}
+
+// write_file - Execute a statement writing inline content to a destination
+// The content is rendered to a file and deployed from there, exactly as
+// a file in the bundle would be
+fn write_file<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
+                                symbols: &mut inter::Symbols<'src>,
+                                log: &mut util::Log<'src>,
+                                run: &mut state::Run,
+                                node: &inter::LinkNode<'inv, 'src>) {
+    let write_children = &node.children();
+    let path = destination(inv, symbols, log, &write_children[0]);
+    if !path.is_absolute() {
+        log.terminal("Written files must be given by absolute path", "Make this path absolute", &write_children[0].tok);
+    }
+    let name = match path.file_name() {
+        Some(name) => name.to_os_string(),
+        None => { log.terminal("Nowhere to write this content", "Name a file to write", &write_children[0].tok); },
+    };
+
+    let value = &write_children[1];
+    let content = match inter::load_value(symbols, value) {
+        inter::Value::Str(ref s) => inter::interpolate(log, symbols, s, value),
+        _ => { log.terminal("Only strings can be written", "Make this a string", value.tok); },
+    };
+
+    let mut attrs = deploy::Attrs::default();
+    let mut only = deploy::Only::ANY;
+    if let Some(mods) = write_children.get(2) {
+        if let Some(m) = modifiers(symbols, log, mods, &mut attrs, &mut only).first() {
+            let tok = if m.is_type(&PTNodeType::ASSIGN) { m.children()[0].tok } else { m.tok };
+            log.terminal("Unknown modifier", "Use a valid modifier such as 'mode' or 'create_only'", tok);
+        }
+    }
+
+    // There is no source in the bundle to capture into
+    if inv.opts.CAPTURE {
+        log.report(deploy::Outcome::SKIPPED.name(), &path);
+        return;
+    }
+
+    let rendering = match template::inline(&name, &content) {
+        Ok(rendering) => rendering,
+        Err(result) => {
+            log.terminal(&format!("Write error: [{}] {}", &result.source, &result.message),
+                         "Modify this line appropriately", &node.tok);
+        },
+    };
+
+    // The rendering is new on every run, so its times mean nothing
+    let mut opts = inv.opts;
+    opts.PRESERVE = deploy::Preserve::NONE;
+
+    if inv.opts.STATUS {
+        match deploy::status(&rendering.path, &deploy::Entity::FILE, &path, deploy::Method::REPLACE, only, opts.PRESERVE, &attrs) {
+            Ok(drift) => {
+                if drift != deploy::Drift::INSYNC {
+                    run.drifted = true;
+                }
+                log.report(drift.name(), &path);
+            },
+            Err(result) => {
+                log.terminal(&format!("Status error: [{}] {}", &result.source, &result.message),
+                             "Modify this line appropriately", &node.tok);
+            },
+        }
+        return;
+    }
+
+    match deploy::deploy(rendering.path.clone(), deploy::Entity::FILE, path.clone(), opts, deploy::Method::REPLACE, only, &attrs, run) {
+        Ok(outcome) => {
+            if !inv.opts.PLAN {
+                log.report(outcome.name(), &path);
+            }
+        },
+        Err(result) => {
+            fail(inv, log, "Write", &result, node.tok);
+        },
+    }
+}
+
 fn execute_stmts<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
                                    symbols: &mut inter::Symbols<'src>,
                                    log: &mut util::Log<'src>,
+                                   run: &mut state::Run,
                                    stmts: Vec<&inter::LinkNode<'inv, 'src>>) {
     let mut scope_names : Vec<&'src str> = vec![];
     for node in stmts {
@@ -68,7 +489,7 @@ fn execute_stmts<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
             PTNodeType::COMMAND => {
                 command(inv, symbols, log, node);
             },
-            PTNodeType::COPY | PTNodeType::INSERT => {
+            PTNodeType::COPY | PTNodeType::INSERT | PTNodeType::LINK => {
                 let deploy_children = &node.children();
                 let src_buf = PathBuf::from(inter::interpolate(log,
                                                                symbols,
@@ -89,70 +510,233 @@ fn execute_stmts<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
                                  &deploy_children[0].tok);
                 }
                 
-                let full_src = inv.root.join(&src_buf);
+                let dst_buf = destination(inv, symbols, log, &deploy_children[1]);
 
-                if !full_src.exists() {
-                    log.terminal(&format!("No entity at source path: {:?}", full_src),
-                                 "Make this a valid path", &deploy_children[0].tok);
-                }
-                
-                let mut dst_buf = PathBuf::from(inter::interpolate(log,
-                                                                   symbols,
-                                                                   &deploy_children[1].token_value(),
-                                                                   &deploy_children[1]));
-
-                let dst_cpy = dst_buf.clone();
-                let dst_comps: Vec<Component> = dst_cpy.components().collect();
-             
-                if dst_comps.len() == 0 {
-                    log.terminal("Destination path is empty (this should not be allowed by the parser)",
-                                 "Put a path here and then please file a bug report!",
-                                 &deploy_children[1].tok);
+                let mut method = if node.is_type(&PTNodeType::LINK) {
+                    deploy::Method::LINK
+                } else {
+                    deploy::Method::REPLACE
+                };
 
+                let mut attrs = deploy::Attrs::default();
+                let mut stmt_exclude = glob::Exclude::new();
+                let mut relative = false;
+                let mut only = deploy::Only::ANY;
+                let mut optional = false;
+                if let Some(mods) = deploy_children.get(2) {
+                    for m in modifiers(symbols, log, mods, &mut attrs, &mut only) {
+                        if m.is_type(&PTNodeType::ASSIGN) {
+                            let m_children = m.children();
+                            let key = &m_children[0];
+                            let val = inter::load_value(symbols, &m_children[1]);
+                            match key.token_value() {
+                                "exclude" if node.is_type(&PTNodeType::LINK) => {
+                                    log.terminal("Links cannot exclude anything",
+                                                 "Deploy this with a copy instead, or remove this modifier", key.tok);
+                                },
+                                "exclude" => {
+                                    let patterns = match val {
+                                        inter::Value::List(vals) => vals,
+                                        val => vec![val],
+                                    };
+                                    for pattern in patterns {
+                                        match pattern {
+                                            inter::Value::Str(ref p) => {
+                                                if let Err(err) = stmt_exclude.add(Path::new(""), p) {
+                                                    log.terminal(&format!("Invalid exclude pattern: {}", err),
+                                                                 "Fix this pattern", m_children[1].tok);
+                                                }
+                                            },
+                                            _ => {
+                                                log.terminal("Exclude patterns must be strings",
+                                                             "Make this a string or list of strings", m_children[1].tok);
+                                            },
+                                        }
+                                    }
+                                },
+                                _ => { log.terminal("Unknown modifier", "Use a valid modifier such as 'exclude' or 'mode'", key.tok); },
+                            }
+                            continue;
+                        }
+                        match m.token_value() {
+                            "merge" | "mirror" if method == deploy::Method::LINK => {
+                                log.terminal("Links cannot be merged or mirrored", "Remove this modifier", m.tok);
+                            },
+                            "merge" | "mirror" if method != deploy::Method::REPLACE => {
+                                log.terminal("Conflicting modifiers", "Keep only one of 'merge' and 'mirror'", m.tok);
+                            },
+                            "merge" => { method = deploy::Method::MERGE; },
+                            "mirror" => { method = deploy::Method::MIRROR; },
+                            "relative" if !node.is_type(&PTNodeType::INSERT) => {
+                                log.terminal("Only insertions keep relative paths", "Remove this modifier", m.tok);
+                            },
+                            "relative" => { relative = true; },
+                            "optional" => { optional = true; },
+                            _ => { log.terminal("Unknown modifier", "Use a valid modifier such as 'merge'", m.tok); },
+                        }
+                    }
+                    if method == deploy::Method::MIRROR && only != deploy::Only::ANY {
+                        log.terminal("Mirrors cannot be create_only or update_only",
+                                     "Remove one of these modifiers", mods.tok);
+                    }
                 }
 
-                dst_buf = if let Ok(dst_tail) = dst_buf.strip_prefix("~") {
-                    dirs::home_dir().unwrap_or_else( || {
-                        log.sys_terminal("Could not find home directory");
-                    }).join(dst_tail)
+                // Globbed sources expand against the bundle, skipping whatever
+                // its .jannignore names
+                let sources = if glob::is_glob(&src_buf) {
+                    let ignore = ignores(inv, Path::new(""), log);
+                    match glob::expand(&inv.root, &src_buf, &ignore) {
+                        Ok(sources) => sources,
+                        Err(err) => {
+                            log.terminal(&format!("Invalid glob: {}", err), "Fix this pattern", &deploy_children[0].tok);
+                        },
+                    }
+                }
+                else if inv.root.join(&src_buf).exists() {
+                    vec![src_buf.clone()]
                 }
                 else {
-                    dst_buf
+                    vec![]
                 };
 
-                if !dst_buf.components().all(|c| match c {
-                    Component::CurDir | Component::ParentDir => false,
-                    _ => true,
-                }) {
-                    log.terminal(&format!("Invalid destination path {:?}", dst_buf),
-                                 "Ensure path is absolute",
-                                 &deploy_children[1].tok);
+                if sources.is_empty() && !optional {
+                    if glob::is_glob(&src_buf) {
+                        log.terminal(&format!("Nothing matches source glob: {:?}", inv.root.join(&src_buf)),
+                                     "Make this match something, or mark it optional", &deploy_children[0].tok);
+                    }
+                    log.terminal(&format!("No entity at source path: {:?}", inv.root.join(&src_buf)),
+                                 "Make this a valid path", &deploy_children[0].tok);
+                }
+                if sources.len() > 1 && !node.is_type(&PTNodeType::INSERT) {
+                    log.terminal("Source glob matches several entities",
+                                 "Insert these with => instead", &deploy_children[0].tok);
                 }
 
-                if node.is_type(&PTNodeType::INSERT) {
-                    let entity = if let Some(parent) = src_buf.parent() {
-                        src_buf.strip_prefix(parent).unwrap()
+                for src_buf in sources {
+                    let full_src = inv.root.join(&src_buf);
+                    let mut dst_buf = dst_buf.clone();
+
+                    if node.is_type(&PTNodeType::INSERT) {
+                        // Relative insertions keep the whole path of the source within the bundle
+                        let entity = if relative {
+                            &src_buf
+                        }
+                        else if let Some(parent) = src_buf.parent() {
+                            src_buf.strip_prefix(parent).unwrap()
+                        }
+                        else {
+                            &src_buf
+                        };
+                        dst_buf = PathBuf::from("/").join(dst_buf.join(template::strip(entity)));
+                    }
+
+
+                    // Directory copies leave out whatever the bundle's .jannignore names,
+                    // and then whatever the statement itself excludes. Links lead
+                    // into the bundle as it stands, so can leave nothing out
+                    let mut exclude = if full_src.is_dir() && method != deploy::Method::LINK {
+                        ignores(inv, &src_buf, log)
                     }
                     else {
-                        &src_buf
+                        glob::Exclude::new()
                     };
-                    dst_buf = PathBuf::from("/").join(dst_buf.join(entity));
-                }
+                    exclude.extend(&stmt_exclude);
+
+                    // Capturing would overwrite a template with its own rendering, so
+                    // one is left alone, as are those within a directory
+                    if inv.opts.CAPTURE && full_src.is_file() && template::is_template(&full_src) {
+                        log.report(deploy::Outcome::SKIPPED.name(), &full_src);
+                        continue;
+                    }
 
-                if let Err(result) = {
-                    if full_src.is_file() {
-                        deploy::deploy(full_src, deploy::Entity::FILE, dst_buf, inv.opts)
+                    // Templates are rendered ahead of time, and deployed from the rendering
+                    if method == deploy::Method::LINK && template::contains(&full_src) {
+                        log.terminal("Templates cannot be linked", "Deploy this with a copy instead", &node.tok);
+                    }
+                    let rendering = if inv.opts.CAPTURE || method == deploy::Method::LINK {
+                        None
                     }
                     else {
-                        deploy::deploy(full_src, deploy::Entity::DIR, dst_buf, inv.opts)
+                        match template::render(symbols, &full_src, &exclude, inv.opts.PRESERVE) {
+                            Ok(rendering) => rendering,
+                            Err(template::RenderError::Template(err)) => {
+                                log.file_terminal(&err.message, &err.hint, &err.file, err.lno, &err.line);
+                            },
+                            Err(template::RenderError::Deploy(result)) => {
+                                log.terminal(&format!("Template error: [{}] {}", &result.source, &result.message),
+                                             "Modify this line appropriately", &node.tok);
+                            },
+                        }
+                    };
+                    let dep_src = match rendering {
+                        Some(ref rendering) => rendering.path.clone(),
+                        None => full_src.clone(),
+                    };
+
+                    if inv.opts.STATUS {
+                        let src_ent = if dep_src.is_file() { deploy::Entity::FILE } else { deploy::Entity::DIR };
+                        let drift = if method == deploy::Method::MIRROR {
+                            deploy::mirror_status(&dep_src, &src_ent, &dst_buf, inv.opts.PRESERVE, &attrs, &exclude)
+                        }
+                        else {
+                            deploy::status(&dep_src, &src_ent, &dst_buf, method, only, inv.opts.PRESERVE, &attrs)
+                        };
+                        match drift {
+                            Ok(drift) => {
+                                if drift != deploy::Drift::INSYNC {
+                                    run.drifted = true;
+                                }
+                                log.report(drift.name(), &dst_buf);
+                            },
+                            Err(result) => {
+                                log.terminal(&format!("Status error: [{}] {}", &result.source, &result.message),
+                                             "Modify this line appropriately", &node.tok);
+                            },
+                        }
+                        continue;
                     }
-                } {
-                    log.terminal(&format!("Deployment error: [{}] {}", &result.source, &result.message),
-                                          "Modify this line appropriately", &node.tok);
-                }
 
+                    if inv.opts.CAPTURE {
+                        match deploy::capture(full_src.clone(), dst_buf, inv.opts, method, only, &exclude, run) {
+                            Ok(outcome) => {
+                                if !inv.opts.PLAN {
+                                    log.report(outcome.name(), &full_src);
+                                }
+                            },
+                            Err(result) => {
+                                log.terminal(&format!("Capture error: [{}] {}", &result.source, &result.message),
+                                             "Modify this line appropriately", &node.tok);
+                            },
+                        }
+                        continue;
+                    }
+
+                    let src_ent = if dep_src.is_file() { deploy::Entity::FILE } else { deploy::Entity::DIR };
+                    let result = if method == deploy::Method::MIRROR {
+                        deploy::mirror(dep_src, src_ent, dst_buf.clone(), inv.opts, &attrs, &exclude, run)
+                    }
+                    else {
+                        deploy::deploy(dep_src, src_ent, dst_buf.clone(), inv.opts, method, only, &attrs, run)
+                    };
+
+                    match result {
+                        Ok(outcome) => {
+                            if !inv.opts.PLAN {
+                                log.report(outcome.name(), &dst_buf);
+                            }
+                        },
+                        Err(result) => {
+                            fail(inv, log, "Deployment", &result, node.tok);
+                        },
+                    }
+                }
+            },
+            PTNodeType::PRESENT | PTNodeType::ABSENT | PTNodeType::SUBST | PTNodeType::SETKEY => {
+                edit_file(inv, symbols, log, run, node);
             },
-            PTNodeType::BLOCK   => { execute_block(inv, symbols, log, node); },
+            PTNodeType::REMOVE  => { remove(inv, symbols, log, run, node); },
+            PTNodeType::WRITE   => { write_file(inv, symbols, log, run, node); },
+            PTNodeType::BLOCK   => { execute_block(inv, symbols, log, run, node); },
             _ => { continue; },
         }
     }
@@ -164,6 +748,7 @@ fn execute_stmts<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
 pub fn execute_block<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
                                        symbols: &mut inter::Symbols<'src>,
                                        log: &mut util::Log<'src>,
+                                       run: &mut state::Run,
                                        node: &inter::LinkNode<'inv, 'src>) {
     let mut block_children = node.children();
     let tag = &block_children[0];
@@ -171,7 +756,7 @@ pub fn execute_block<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
     match tag.ptn.nt {
         PTNodeType::NAME => {
             if inter::check_name(tag.token_value()) {
-                execute_stmts(inv, symbols, log, block_children.iter().skip(1).collect());
+                execute_stmts(inv, symbols, log, run, block_children.iter().skip(1).collect());
             }
             else {
                 log.terminal("Invalid Block Name", "Choose a valid name for this block", &tag.tok);
@@ -190,7 +775,7 @@ pub fn execute_block<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
                             elem
                         };
                         symbols.names.insert(&name, elem);
-                        execute_stmts(inv, symbols, log, block_children.iter().skip(1).collect());
+                        execute_stmts(inv, symbols, log, run, block_children.iter().skip(1).collect());
                     }
                     symbols.names.remove(name);
                 }
@@ -213,9 +798,18 @@ pub fn execute_block<'inv, 'src: 'inv>(inv: &invoke::Invocation<'src>,
                 let path = inter::interpolate(log, symbols, &path, pval);
                 let cur = env::current_dir().unwrap();
                 let path = cur.join(path);
-                if path.is_dir() {
+                if inv.opts.PLAN || inv.opts.STATUS || inv.opts.CAPTURE || inv.opts.ARCHIVE {
+                    // The directory may be made by a command we are not running,
+                    // so plan the block from here if we cannot enter it
+                    if inv.opts.PLAN { println!("[   Plan] cd {:?}", path); }
+                    if path.is_dir() {
+                        let _ = env::set_current_dir(&path);
+                    }
+                    execute_stmts(inv, symbols, log, run, block_children.iter().skip(1).collect());
+                }
+                else if path.is_dir() {
                     if let Ok(_) = env::set_current_dir(path) {
-                        execute_stmts(inv, symbols, log, block_children.iter().skip(1).collect()); 
+                        execute_stmts(inv, symbols, log, run, block_children.iter().skip(1).collect()); 
                     }
                     else {
                         log.terminal("Could not set working directory", "Make this an accessible directory", pval.tok);
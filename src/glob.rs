@@ -0,0 +1,381 @@
+extern crate regex;
+
+use std::path::{Path, PathBuf};
+
+// Patterns follow the conventions of .gitignore
+// - Blank lines and lines starting with '#' are ignored
+// - A leading '!' re-includes whatever an earlier pattern excluded
+// - A trailing '/' only matches directories
+// - A pattern with any other '/' is anchored, otherwise it matches at any depth
+// - '*' and '?' match within a component, and '**' across components
+#[derive(Clone)]
+struct Pattern {
+    regex: regex::Regex,
+    negate: bool,
+    dir_only: bool,
+}
+
+// translate - Convert the body of a pattern into a regular expression
+fn translate(body: &str) -> Result<String, String> {
+    let chars: Vec<char> = body.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    out.push_str("(?:.*/)?");
+                    i += 3;
+                }
+                else {
+                    out.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            },
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => {
+                let close = match chars.iter().skip(i + 2).position(|c| *c == ']') {
+                    Some(n) => i + 2 + n,
+                    None => { return Err(String::from("Unclosed character class")); },
+                };
+                out.push('[');
+                let mut class = &chars[i + 1..close];
+                if class.first() == Some(&'!') {
+                    out.push('^');
+                    class = &class[1..];
+                }
+                for c in class {
+                    if *c == '\\' || *c == '[' {
+                        out.push('\\');
+                    }
+                    out.push(*c);
+                }
+                out.push(']');
+                i = close;
+            },
+            '\\' if i + 1 < chars.len() => {
+                out.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 1;
+            },
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    Ok(out)
+}
+
+impl Pattern {
+    fn new(line: &str) -> Result<Option<Pattern>, String> {
+        let mut body = line.trim_end();
+        if body.is_empty() || body.starts_with('#') {
+            return Ok(None);
+        }
+
+        let negate = body.starts_with('!');
+        if negate {
+            body = &body[1..];
+        }
+        let dir_only = body.ends_with('/');
+        if dir_only {
+            body = &body[..body.len() - 1];
+        }
+        let anchored = body.contains('/');
+        if anchored {
+            body = body.trim_start_matches('/');
+        }
+        if body.is_empty() {
+            return Err(String::from("Pattern matches nothing"));
+        }
+
+        let prefix = if anchored { "^" } else { "^(?:.*/)?" };
+        let regex = regex::Regex::new(&format!("{}{}$", prefix, translate(body)?))
+                                 .map_err(|e| format!("{}", e))?;
+        Ok(Some(Pattern { regex, negate, dir_only }))
+    }
+}
+
+// Exclude - A set of patterns naming entities to leave out of a copy
+// Each pattern is matched against a path relative to some base, so that
+// patterns from the bundle's .jannignore apply relative to the bundle,
+// while those given on a statement apply relative to its source
+#[derive(Clone)]
+pub struct Exclude {
+    patterns: Vec<(PathBuf, Pattern)>,
+}
+
+impl Exclude {
+    pub fn new() -> Exclude {
+        Exclude { patterns: vec![] }
+    }
+
+    pub fn add(&mut self, base: &Path, line: &str) -> Result<(), String> {
+        if let Some(pattern) = Pattern::new(line)? {
+            self.patterns.push((base.to_path_buf(), pattern));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    // extend - Add the patterns of another set after those of this one
+    pub fn extend(&mut self, other: &Exclude) {
+        self.patterns.extend(other.patterns.iter().cloned());
+    }
+
+    // excludes - Determine whether an entity within a source is left out
+    // As in git, the last pattern to match has the final say
+    pub fn excludes(&self, rel: &Path, is_dir: bool) -> bool {
+        let mut excluded = false;
+        for (base, pattern) in &self.patterns {
+            if pattern.dir_only && !is_dir {
+                continue;
+            }
+            let path = base.join(rel);
+            if pattern.regex.is_match(&path.to_string_lossy()) {
+                excluded = !pattern.negate;
+            }
+        }
+        excluded
+    }
+}
+
+// Sources may also be globs, matched against the bundle component by
+// component as a shell would, so that wildcards do not match hidden names
+// unless the component itself starts with '.'
+
+pub fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+// expand - Find the entities within root matched by a glob
+// Matches are relative to root, in order, and skip anything ignored
+pub fn expand(root: &Path, glob: &Path, ignore: &Exclude) -> Result<Vec<PathBuf>, String> {
+    let comps: Vec<String> = glob.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+    let mut matches = vec![];
+    walk(root, PathBuf::new(), &comps, ignore, &mut matches)?;
+    matches.sort();
+    matches.dedup();
+    Ok(matches)
+}
+
+fn walk(root: &Path, rel: PathBuf, comps: &[String], ignore: &Exclude, matches: &mut Vec<PathBuf>) -> Result<(), String> {
+    let comp = match comps.first() {
+        Some(comp) => comp,
+        None => {
+            matches.push(rel);
+            return Ok(());
+        },
+    };
+
+    if !is_glob(Path::new(comp)) {
+        let next = rel.join(comp);
+        if root.join(&next).symlink_metadata().is_ok() && !ignore.excludes(&next, root.join(&next).is_dir()) {
+            walk(root, next, &comps[1..], ignore, matches)?;
+        }
+        return Ok(());
+    }
+
+    let regex = if comp == "**" {
+        None
+    }
+    else {
+        Some(regex::Regex::new(&format!("^{}$", translate(comp)?)).map_err(|e| format!("{}", e))?)
+    };
+    // '**' may match no components at all
+    if regex.is_none() {
+        walk(root, rel.clone(), &comps[1..], ignore, matches)?;
+    }
+
+    let entries = match root.join(&rel).read_dir() {
+        Ok(entries) => entries,
+        Err(_) => { return Ok(()); },
+    };
+    let mut names: Vec<String> = entries.filter_map(|e| e.ok())
+                                        .map(|e| e.file_name().to_string_lossy().to_string())
+                                        .collect();
+    names.sort();
+    for name in names {
+        if name.starts_with('.') && !comp.starts_with('.') {
+            continue;
+        }
+        let next = rel.join(&name);
+        let is_dir = root.join(&next).is_dir();
+        if ignore.excludes(&next, is_dir) {
+            continue;
+        }
+        match regex {
+            Some(ref regex) => {
+                if regex.is_match(&name) {
+                    walk(root, next, &comps[1..], ignore, matches)?;
+                }
+            },
+            None => {
+                if is_dir {
+                    walk(root, next, comps, ignore, matches)?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::env;
+    use std::process;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        regex::Regex::new(&format!("^{}$", translate(pattern).unwrap())).unwrap().is_match(path)
+    }
+
+    fn exclude(lines: &[&str]) -> Exclude {
+        let mut exclude = Exclude::new();
+        for line in lines {
+            exclude.add(Path::new(""), line).unwrap();
+        }
+        exclude
+    }
+
+    #[test]
+    fn translate_wildcards_stay_within_components() {
+        assert!(matches("*.conf", "app.conf"));
+        assert!(!matches("*.conf", "dir/app.conf"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "a/c"));
+    }
+
+    #[test]
+    fn translate_double_star_spans_components() {
+        assert!(matches("**/foo", "foo"));
+        assert!(matches("**/foo", "a/b/foo"));
+        assert!(matches("a/**", "a/b/c"));
+        assert!(!matches("**/foo", "afoo"));
+    }
+
+    #[test]
+    fn translate_classes_and_escapes() {
+        assert!(matches("[ab].txt", "a.txt"));
+        assert!(!matches("[!ab].txt", "a.txt"));
+        assert!(matches("[!ab].txt", "c.txt"));
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+        assert!(matches("a.b", "a.b"));
+        assert!(!matches("a.b", "axb"));
+        assert!(translate("[ab").is_err());
+    }
+
+    #[test]
+    fn patterns_skip_blanks_and_comments() {
+        assert!(exclude(&["", "# *.log", "   "]).is_empty());
+    }
+
+    #[test]
+    fn unanchored_patterns_match_at_any_depth() {
+        let ex = exclude(&["*.log"]);
+        assert!(ex.excludes(Path::new("x.log"), false));
+        assert!(ex.excludes(Path::new("a/b/x.log"), false));
+        assert!(!ex.excludes(Path::new("x.logs"), false));
+    }
+
+    #[test]
+    fn anchored_patterns_match_from_the_base() {
+        let ex = exclude(&["/build", "doc/*.html"]);
+        assert!(ex.excludes(Path::new("build"), true));
+        assert!(!ex.excludes(Path::new("src/build"), true));
+        assert!(ex.excludes(Path::new("doc/index.html"), false));
+        assert!(!ex.excludes(Path::new("src/doc/index.html"), false));
+    }
+
+    #[test]
+    fn trailing_slash_only_matches_directories() {
+        let ex = exclude(&["cache/"]);
+        assert!(ex.excludes(Path::new("cache"), true));
+        assert!(!ex.excludes(Path::new("cache"), false));
+    }
+
+    #[test]
+    fn negated_patterns_reinclude() {
+        let ex = exclude(&["*.log", "!keep.log"]);
+        assert!(ex.excludes(Path::new("x.log"), false));
+        assert!(!ex.excludes(Path::new("keep.log"), false));
+        // The last pattern to match has the final say
+        let ex = exclude(&["!keep.log", "*.log"]);
+        assert!(ex.excludes(Path::new("keep.log"), false));
+    }
+
+    #[test]
+    fn patterns_apply_relative_to_their_base() {
+        let mut ex = Exclude::new();
+        ex.add(Path::new("conf"), "/conf/secret").unwrap();
+        assert!(ex.excludes(Path::new("secret"), false));
+        assert!(!ex.excludes(Path::new("other"), false));
+    }
+
+    // bundle - Lay out a scratch tree of empty files, removed once dropped
+    struct Bundle(PathBuf);
+
+    impl Bundle {
+        fn new(name: &str, files: &[&str]) -> Bundle {
+            let root = env::temp_dir().join(format!("jann-{}-test-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&root);
+            for file in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, "").unwrap();
+            }
+            Bundle(root)
+        }
+
+        fn expand(&self, glob: &str, ignore: &Exclude) -> Vec<String> {
+            expand(&self.0, Path::new(glob), ignore).unwrap()
+                .iter().map(|p| p.to_string_lossy().to_string()).collect()
+        }
+    }
+
+    impl Drop for Bundle {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn expand_matches_in_order() {
+        let b = Bundle::new("order", &["b.conf", "a.conf", "c.txt"]);
+        assert_eq!(b.expand("*.conf", &Exclude::new()), vec!["a.conf", "b.conf"]);
+    }
+
+    #[test]
+    fn expand_skips_hidden_names_unless_asked() {
+        let b = Bundle::new("hidden", &[".x.conf", "y.conf"]);
+        assert_eq!(b.expand("*.conf", &Exclude::new()), vec!["y.conf"]);
+        assert_eq!(b.expand(".*.conf", &Exclude::new()), vec![".x.conf"]);
+    }
+
+    #[test]
+    fn expand_double_star_matches_zero_components() {
+        let b = Bundle::new("star", &["f.conf", "a/f.conf", "a/b/f.conf", "a/b/g.txt"]);
+        assert_eq!(b.expand("**/f.conf", &Exclude::new()), vec!["a/b/f.conf", "a/f.conf", "f.conf"]);
+    }
+
+    #[test]
+    fn expand_follows_literal_components() {
+        let b = Bundle::new("literal", &["a/x.conf", "b/x.conf"]);
+        assert_eq!(b.expand("a/*.conf", &Exclude::new()), vec!["a/x.conf"]);
+        assert!(b.expand("c/*.conf", &Exclude::new()).is_empty());
+    }
+
+    #[test]
+    fn expand_skips_ignored_entities() {
+        let b = Bundle::new("ignore", &["keep.conf", "skip.conf", "old/x.conf"]);
+        let mut ignore = Exclude::new();
+        ignore.add(Path::new(""), "skip.conf").unwrap();
+        ignore.add(Path::new(""), "old/").unwrap();
+        assert_eq!(b.expand("**/*.conf", &ignore), vec!["keep.conf"]);
+    }
+}
@@ -106,6 +106,26 @@ pub fn interpolate<'inv, 'src: 'inv>(log: &mut util::Log<'src>,
                                      symbols: &Symbols<'src>,
                                      base: &'inv str,
                                      node: &LinkNode<'inv, 'src>) -> String {
+    match expand(symbols, base) {
+        Ok(outstr) => outstr,
+        Err((msg, hint)) => { log.terminal(&msg, &hint, &node.tok); },
+    }
+}
+
+// Perform the substitution itself, returning a message and hint on failure
+// so that callers can point at wherever the base string came from
+
+pub fn expand(symbols: &Symbols, base: &str) -> Result<String, (String, String)> {
+    substitute(symbols, base, false)
+}
+
+// Substitute into a line of a template, where a backslash means nothing
+// unless it escapes a brace, so that the file is otherwise left as written
+pub fn expand_template(symbols: &Symbols, base: &str) -> Result<String, (String, String)> {
+    substitute(symbols, base, true)
+}
+
+fn substitute(symbols: &Symbols, base: &str, template: bool) -> Result<String, (String, String)> {
     // A mini enumeration of parsing states
     let NONE = 0;
     let LBRACE = 1;
@@ -123,10 +143,11 @@ pub fn interpolate<'inv, 'src: 'inv>(log: &mut util::Log<'src>,
     let mut name: String = "".to_string();
 
     // We parse on a char-by-char basis
-    for c in base.chars() {
+    let mut chars = base.chars().peekable();
+    while let Some(c) = chars.next() {
         if ex == RBRACE {
             if c != '}' {
-                log.terminal("Expected right brace", "Missing right brace", &node.tok);
+                return Err(("Expected right brace".to_string(), "Missing right brace".to_string()));
             }
             ex = NONE;
             continue;
@@ -134,18 +155,19 @@ pub fn interpolate<'inv, 'src: 'inv>(log: &mut util::Log<'src>,
 
         if ex == WITHIN {
             if c == '}' {
-                let val = symbols.names.get(name.trim()).unwrap_or_else( || {
-                    symbols.jnames.get(name.trim()).unwrap_or_else( || {
-                        log.terminal(&format!("No such variable {}", name),
-                                     "Ensure interpolation uses extant, in-scope variables", &node.tok);
-                    })
-                });
+                let val = match symbols.names.get(name.trim()).or_else(|| symbols.jnames.get(name.trim())) {
+                    Some(val) => val,
+                    None => {
+                        return Err((format!("No such variable {}", name),
+                                    "Ensure interpolation uses extant, in-scope variables".to_string()));
+                    },
+                };
                 if let Value::Str(ref v) = val  {
                     outstr.push_str(v);
                 }
                 else {
-                    log.terminal("Only strings can be interpolated into commands",
-                                 &format!("Change the type of variable {}", name.trim()), &node.tok);
+                    return Err(("Only strings can be interpolated into commands".to_string(),
+                                format!("Change the type of variable {}", name.trim())));
                 }
                 name = "".to_string();
                 ex = RBRACE;
@@ -167,7 +189,7 @@ pub fn interpolate<'inv, 'src: 'inv>(log: &mut util::Log<'src>,
         
         }
 
-        if c == '\\' && !esc {
+        if c == '\\' && !esc && (!template || chars.peek() == Some(&'{')) {
             esc = true;
             continue;
         }
@@ -176,16 +198,21 @@ pub fn interpolate<'inv, 'src: 'inv>(log: &mut util::Log<'src>,
            continue;
         }
 
+        // An escaped newline continues the line, so vanishes altogether
+        if esc && c == '\n' {
+            esc = false;
+            continue;
+        }
         if esc { esc = false; }
 
         outstr.push(c);
     }
 
     if ex != NONE {
-        log.terminal("Bad interpolation syntax", "Make sure all braces are matched", &node.tok);
+        return Err(("Bad interpolation syntax".to_string(), "Make sure all braces are matched".to_string()));
     }
 
-    outstr
+    Ok(outstr)
 }
 
 pub fn load_value<'old, 'src: 'old>(symbols: &Symbols<'src>,
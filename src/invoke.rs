@@ -4,10 +4,11 @@ use inter;
 use exec;
 use deploy;
 use util;
+use state;
 
 use std::fs;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use std::process::Command;
 
@@ -42,6 +43,7 @@ impl<'inv, 'src: 'inv> Pipeline<'src> {
                inv: &Invocation<'src>,
                symbols: &mut inter::Symbols<'src>,
                log: &mut util::Log<'src>,
+               run: &mut state::Run,
                tab: usize,
                ) {
 
@@ -62,7 +64,7 @@ impl<'inv, 'src: 'inv> Pipeline<'src> {
             // Note that we increment the tab count
             if let Some(ptr) = flow.lines[pl_self].stages[st_index].pl_ptr {
                 println!("[Running] {} | {}", tabs, flow.lines[pl_self].stages[st_index].name);
-                Pipeline::execute(flow, ptr, inv, symbols, log, tab + 1);
+                Pipeline::execute(flow, ptr, inv, symbols, log, run, tab + 1);
             }
             else {
                 // If it's not a Pipeline it's either a block or an external reference
@@ -76,8 +78,9 @@ impl<'inv, 'src: 'inv> Pipeline<'src> {
 
                         // If it's a block, we execute it
                         if let Some(block_id) = symbols.blocks.get(name) {
+                            run.enter(name);
                             let mut node: inter::LinkNode = inv.art.node(*block_id);
-                            exec::execute_block(inv, symbols, log, &node);
+                            exec::execute_block(inv, symbols, log, run, &node);
                         }
                         
                         // Otherwise, it might be an 'include' - a reference to an external file
@@ -99,17 +102,29 @@ impl<'inv, 'src: 'inv> Pipeline<'src> {
                             // Now we can create a new jann process to run the included file
                             // Note that the included file recieves no state
                             
-                            let optstr = inv.opts.dump();
+                            let mut optstr: Vec<String> = inv.opts.dump().iter().map(|o| o.to_string()).collect();
+                            if let Some(ref target) = inv.target {
+                                optstr.push(String::from("--target-root"));
+                                optstr.push(target.to_string_lossy().to_string());
+                            }
 
                             let incl_msg = format!("********** Include: {}::{} *********", &file, &entry);
                             println!("\n{}", incl_msg);
                             
                             let mut proc = if *sudo {
+                                // Its journal is root's, so ours points the way there
+                                if !inv.opts.PLAN && !inv.opts.STATUS {
+                                    if let Err(err) = run.elevate(Path::new(&jannfile)) {
+                                        log.sys_terminal(&format!("[{}] {}", err.source, err.message));
+                                    }
+                                }
                                 Command::new("sudo")
                                     .current_dir(&inv.root)
                                     .arg(binary)
                                     .arg(jannfile)
-                                    .args(optstr)
+                                    .args(&optstr)
+                                    .arg("--run")
+                                    .arg(&run.id)
                                     .arg("--execute")
                                     .arg(entry)
                                     .spawn()
@@ -119,14 +134,21 @@ impl<'inv, 'src: 'inv> Pipeline<'src> {
                                 Command::new(binary)
                                     .current_dir(&inv.root)
                                     .arg(file)
-                                    .args(optstr)
+                                    .args(&optstr)
+                                    .arg("--run")
+                                    .arg(&run.id)
                                     .arg("--execute")
                                     .arg(entry)
                                     .spawn()
                                     .expect("Failed to run included Jannfile")
                             };
                             
-                            if !proc.wait().expect("Failed to wait on Jann").success() {
+                            // Under --status an included Jannfile exits with 2 if it has drifted
+                            let status = proc.wait().expect("Failed to wait on Jann");
+                            if inv.opts.STATUS && status.code() == Some(2) {
+                                run.drifted = true;
+                            }
+                            else if !status.success() {
                                 println!("{}", "*".repeat(incl_msg.len()));
                                 log.die();
                             };
@@ -161,11 +183,11 @@ impl<'inv, 'src: 'inv> Workflow<'src> {
         Workflow { lines: vec![], index: HashMap::new() }
     }
 
-    fn execute(&mut self, inv: &Invocation<'src>, symbols: &mut inter::Symbols<'src>, log: &mut util::Log<'src>) {
+    fn execute(&mut self, inv: &Invocation<'src>, symbols: &mut inter::Symbols<'src>, log: &mut util::Log<'src>, run: &mut state::Run) {
         let mut main_line = self.index.get(inv.pl_name.as_str()).unwrap_or_else( | | {
             log.sys_terminal("No such pipeline exists.");
         });
-        Pipeline::execute(self, *main_line, inv, symbols, log, 0);
+        Pipeline::execute(self, *main_line, inv, symbols, log, run, 0);
     }
 }
 
@@ -173,6 +195,7 @@ impl<'inv, 'src: 'inv> Workflow<'src> {
 pub struct Invocation<'src> {
     pub root : PathBuf,
     pub edir : PathBuf,
+    pub target : Option<PathBuf>,
     pub opts : deploy::DepOpt,
     pub pl_name : String, 
     pub art  : inter::Artifact<'src>,
@@ -181,7 +204,7 @@ pub struct Invocation<'src> {
 
 
 impl<'inv, 'src: 'inv> Invocation<'src> {
-    pub fn invoke(self, log: &'inv mut util::Log<'src>) {
+    pub fn invoke(self, log: &'inv mut util::Log<'src>, run: &mut state::Run) {
 
         // Set up directories
 
@@ -214,6 +237,15 @@ impl<'inv, 'src: 'inv> Invocation<'src> {
                                  log.sys_terminal("Unable to handle bundle path");                            
                              })));
 
+        // Commands may place things under the target root too, so it is
+        // made available to them, and is empty when there is none
+        // Archived commands run once the archive is unpacked at /, so see none
+        let target = match self.target {
+            Some(ref target) if !self.opts.ARCHIVE => target.to_string_lossy().to_string(),
+            _ => String::new(),
+        };
+        symbols.jnames.insert("TARGET", inter::Value::Str(target));
+
         let root = self.art.root();
         let mut flow = Workflow::new();
 
@@ -280,6 +312,7 @@ impl<'inv, 'src: 'inv> Invocation<'src> {
             let tag = &child.children()[0];
             if tag.is_type(&PTNodeType::NAME) {
                 symbols.blocks.insert(tag.token_value(), child.ptn.id);
+                run.define(tag.token_value());
             }
 
             // Blocks just need to be added to the Symbol table, but
@@ -417,7 +450,7 @@ impl<'inv, 'src: 'inv> Invocation<'src> {
         }
         
         // Light the blue touch-paper!
-        flow.execute(&self, &mut symbols, log);
+        flow.execute(&self, &mut symbols, log, run);
 
         env::set_current_dir(&cwd).unwrap_or_else( | _ | { 
             log.sys_terminal("Could not change CWD!");
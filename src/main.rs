@@ -1,8 +1,9 @@
 use std::io;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{PathBuf, Component};
 use std::env;
 use std::process;
+use std::fs;
 use std::fs::File;
 
 mod com;
@@ -12,13 +13,20 @@ mod invoke;
 mod exec;
 mod inter;
 mod deploy;
+mod state;
+mod diff;
+mod manifest;
+mod template;
+mod glob;
+mod archive;
+mod edit;
 
 fn main() {
     /* Parse command line arguments */
     
     let command = com::Command::new();
 
-    let (lines, switches, job) = match command {
+    let (source, switches, job) = match command {
         com::Command::HELP { code } => {
             println!("jann - Configuration deployment utility for *nix");
             process::exit(code);
@@ -27,38 +35,53 @@ fn main() {
             println!("jann v0.1.0");
             process::exit(code);
         },
+        com::Command::RESTORE { run } => {
+            let lines = vec![];
+            let mut log = util::Log::new(String::from("restore"), &lines);
+            if let Err(err) = state::restore(&run) {
+                log.sys_terminal(&format!("[{}] {}", err.source, err.message));
+            }
+            log.conclude();
+        },
+        com::Command::ROLLBACK { run } => {
+            let lines = vec![];
+            let mut log = util::Log::new(String::from("rollback"), &lines);
+            if let Err(err) = state::rollback(run.as_deref()) {
+                log.sys_terminal(&format!("[{}] {}", err.source, err.message));
+            }
+            log.conclude();
+        },
         com::Command::DO_STDIN { switches } => {
-            let stdin = io::stdin();
-            let lines: Vec<String> = stdin.lock().lines().map(|l| l.unwrap()).collect();
-            (lines, switches, String::from("stdin"))
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source).unwrap();
+            (source, switches, String::from("stdin"))
         },
         com::Command::DO_FILE { switches, file: path } => {
             let file = File::open(&path);
-            let file = file.unwrap_or_else( |_| {
+            let mut file = file.unwrap_or_else( |_| {
                 println!("error: no such file {}", path);
                 process::exit(66);
             });
-            let reader = BufReader::new(file);
-            let lines: Vec<String> = reader.lines().map(|l| l.unwrap()).collect();
-            (lines, switches, path)
+            let mut source = String::new();
+            file.read_to_string(&mut source).unwrap();
+            (source, switches, path)
         },
     };
 
     // println!("Switches: {:?}", switches);
     
 
+    // Jannfiles read from stdin have no lasting identity to keep a manifest under
+    let jannfile = if job == "stdin" { None } else { fs::canonicalize(&job).ok() };
+
+    let source = parse::normalise(&source);
+
+    let lines: Vec<String> = source.lines().map(String::from).collect();
     let mut log = util::Log::new(job, &lines);
 
     /* Tokenise input data */
 
-    let mut toks = vec![];
-
-    let mut id: usize = 1;
-    let mut lno: usize = 1;
-    for index in 0..(lines.len()) {
-        toks.extend(parse::tokenise(&mut log, lno, &mut id, &lines[index]));
-        lno += 1;
-    }
+    let toks = parse::tokenise(&mut log, &source);
     if log.has_err() {
         log.conclude();
     }
@@ -77,7 +100,12 @@ fn main() {
     /* Get entry-point */
 
     let mut pl_name = String::from("main");
-    let mut opts = deploy::DepOpt { OW_FF: true, OW_DD: true, OW_FD: false, OW_DF: true, INTER: true};
+    let mut run_id = None;
+    let mut target = None;
+    let mut opts = deploy::DepOpt { OW_FF: true, OW_DD: true, OW_FD: false, OW_DF: true, INTER: true,
+                                    PLAN: false, DIFF: false, ASK: false, PRUNE: false, STATUS: false,
+                                    CAPTURE: false, ARCHIVE: false, PRESERVE: deploy::Preserve::NONE };
+    let mut archive = None;
     for (com, refs) in &switches {
         match (*com).as_ref() {
             "execute" => {
@@ -99,6 +127,56 @@ fn main() {
                     }
                 }
             },
+            "run" => {
+                if let Some(com::Reference::RUN(id)) = refs.get(0) {
+                    run_id = Some(id.to_string());
+                }
+            },
+            // An included Jannfile is given no archive of its own, and
+            // instead stages into the target root of the one including it
+            "archive" => {
+                opts.ARCHIVE = true;
+                if let Some(com::Reference::PATH(path)) = refs.get(0) {
+                    archive = Some(PathBuf::from(path));
+                }
+            },
+            "target-root" => {
+                if let Some(com::Reference::PATH(path)) = refs.get(0) {
+                    target = Some(PathBuf::from(path));
+                }
+            },
+            "plan" => {
+                opts.PLAN = true;
+            },
+            "diff" => {
+                opts.DIFF = true;
+            },
+            "interactive" => {
+                opts.ASK = true;
+            },
+            "prune" => {
+                opts.PRUNE = true;
+            },
+            "status" => {
+                opts.STATUS = true;
+            },
+            "capture" => {
+                // Captures always show what they bring back into the bundle
+                opts.CAPTURE = true;
+                opts.DIFF = true;
+            },
+            "preserve" => {
+                for r in refs {
+                    if let com::Reference::FLAG(r) = r {
+                        match r.to_lowercase().as_ref() {
+                            "links" => {opts.PRESERVE.LINKS = true;},
+                            "modes" => {opts.PRESERVE.MODES = true;},
+                            "times" => {opts.PRESERVE.TIMES = true;},
+                            _ => (),
+                        }
+                    }
+                }
+            },
             "forbid" => {
                  for r in refs {
                     if let com::Reference::FLAG(r) = r {
@@ -117,21 +195,90 @@ fn main() {
         }
     }
 
+    // An archive carries the bundle's own modes, whatever the umask of its builder
+    if opts.ARCHIVE {
+        opts.PRESERVE.MODES = true;
+    }
+
     /* Execute parsed Jannfile */
 
     let art = inter::Artifact::new(&toks, &tree);
     let cwd = env::current_dir().expect("Could not get cwd"); 
     // use ./deploy as execution directory for now
     let edir = cwd.join("deploy"); 
+    let stage = env::temp_dir().join(format!("jann-{}-archive", process::id()));
+    let archive = archive.map(|a| cwd.join(a));
+    if let Some(ref archive) = archive {
+        if target.is_some() {
+            log.sys_terminal("An archive cannot also have a target root");
+        }
+        if !archive.parent().is_some_and(|p| p.is_dir()) {
+            log.sys_terminal(&format!("No directory to write archive {:?} into", archive));
+        }
+        let _ = fs::remove_dir_all(&stage);
+        target = Some(stage.clone());
+    }
+
+    // Destinations are resolved under the target root, which need not exist yet
+    let target = target.map(|t| {
+        let mut abs = PathBuf::new();
+        for c in cwd.join(t).components() {
+            match c {
+                Component::ParentDir => { abs.pop(); },
+                Component::CurDir => (),
+                c => abs.push(c),
+            }
+        }
+        abs
+    });
+    // Included Jannfiles are handed the stage as their target root, but only
+    // the Jannfile writing the archive may clear it away
+    if let (true, Some(stage)) = (opts.ARCHIVE, target.as_ref()) {
+        log.stage(stage, archive.is_some());
+    }
     let inv = invoke::Invocation {
         root: cwd,
         edir,
+        target: target.clone(),
         opts,
         pl_name,
         art: art,
         switches: switches,
     };
-    inv.invoke(&mut log);
+    let entry = inv.pl_name.clone();
+
+    let mut run = state::Run::new(run_id);
+    // Nothing staged for an archive needs undoing
+    run.journal = !opts.ARCHIVE;
+    inv.invoke(&mut log, &mut run);
+
+    // Included Jannfiles stage into the same place, so each notes what it deployed
+    if let (true, Some(stage)) = (opts.ARCHIVE, target.as_ref()) {
+        let produced: Vec<PathBuf> = run.produced().iter().map(|(_, p)| p.clone()).collect();
+        if let Err(err) = archive::note(stage, &produced) {
+            log.sys_terminal(&format!("[{}] {}", err.source, err.message));
+        }
+    }
+
+    if let Some(archive) = archive {
+        let result = fs::create_dir_all(&stage).map_err(deploy::DeployError::from)
+                                               .and_then(|_| archive::write(&stage, &archive));
+        if let Err(err) = result {
+            log.sys_terminal(&format!("[{}] {}", err.source, err.message));
+        }
+        println!("[Archive] {:?}", archive);
+    }
+
+    if opts.STATUS {
+        log.conclude_status(run.drifted);
+    }
+
+    // A capture deploys nothing, so leaves the manifest as it was, as does an archive
+    if let (Some(jannfile), false) = (jannfile, opts.CAPTURE || opts.ARCHIVE) {
+        if let Err(err) = manifest::settle(&jannfile, &entry, target.as_deref(), opts, &mut log, &mut run) {
+            log.sys_terminal(&format!("[{}] {}", err.source, err.message));
+        }
+    }
 
     log.conclude();
 }
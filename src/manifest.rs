@@ -0,0 +1,138 @@
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeSet;
+
+use deploy::{self, DeployError, DepOpt};
+use state;
+use util;
+
+// Each Jannfile pipeline keeps a manifest in <state>/manifests/ listing
+// every destination it deployed, one per line as the block that deployed it
+// and its path, separated by a tab. Comparing this against what a run
+// produces reveals orphans - destinations whose lines have since been
+// deleted from the Jannfile. Only the blocks which ran can tell.
+
+// manifest_file - Find the manifest for a pipeline of a Jannfile
+// The Jannfile path is escaped so that it can serve as a file name
+// Deployments into a target root keep a manifest of their own, so that
+// staging never prunes what was deployed for real
+fn manifest_file(jannfile: &Path, pipeline: &str, target: Option<&Path>) -> Result<PathBuf, DeployError> {
+    let dir = match util::state_dir() {
+        Some(dir) => dir.join("manifests"),
+        None => { return Err(DeployError::locked("Manifest", "Could not find a state directory")); },
+    };
+    let escape = |path: &Path, name: &mut Vec<u8>| {
+        for b in path.as_os_str().as_bytes() {
+            match *b {
+                b'%' => name.extend_from_slice(b"%25"),
+                b'/' => name.extend_from_slice(b"%2F"),
+                b => name.push(b),
+            }
+        }
+    };
+    let mut name = vec![];
+    escape(jannfile, &mut name);
+    name.extend_from_slice(format!("::{}", pipeline).as_bytes());
+    if let Some(target) = target {
+        name.push(b'@');
+        escape(target, &mut name);
+    }
+    Ok(dir.join(OsStr::from_bytes(&name)))
+}
+
+// read - Load a manifest, in which a path alone belongs to no known block
+fn read(file: &Path) -> BTreeSet<(String, PathBuf)> {
+    let entry = |line: &[u8]| match line.iter().position(|b| *b == b'\t') {
+        Some(tab) => (String::from_utf8_lossy(&line[..tab]).to_string(),
+                      PathBuf::from(OsStr::from_bytes(&line[tab + 1..]))),
+        None => (String::new(), PathBuf::from(OsStr::from_bytes(line))),
+    };
+    match fs::read(file) {
+        Ok(data) => data.split(|b| *b == b'\n')
+                        .filter(|l| !l.is_empty())
+                        .map(entry)
+                        .collect(),
+        Err(_) => BTreeSet::new(),
+    }
+}
+
+fn write(file: &Path, entries: &BTreeSet<(String, PathBuf)>) -> Result<(), DeployError> {
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut data = vec![];
+    for (block, path) in entries {
+        data.extend_from_slice(block.as_bytes());
+        data.push(b'\t');
+        data.extend_from_slice(path.as_os_str().as_bytes());
+        data.push(b'\n');
+    }
+    // Write beside the manifest and rename, so it is never left half-written
+    let mut temp = file.as_os_str().to_owned();
+    temp.push(".new");
+    fs::write(&temp, data)?;
+    fs::rename(&temp, file)?;
+    Ok(())
+}
+
+// settle - Compare what a run deployed with what the last run deployed
+// Orphans are removed when pruning (backed up under the run, like any
+// overwrite), and otherwise reported and kept in the manifest for later
+// Entries of blocks which were disabled, or otherwise did not run, are kept
+// as they stand, since there is no telling whether their lines remain
+pub fn settle(jannfile: &Path, pipeline: &str, target: Option<&Path>, opt: DepOpt, log: &mut util::Log, run: &mut state::Run) -> Result<(), DeployError> {
+    let file = manifest_file(jannfile, pipeline, target)?;
+    let mut entries: BTreeSet<(String, PathBuf)> = run.produced().iter().cloned().collect();
+    let produced: BTreeSet<PathBuf> = entries.iter().map(|(_, p)| p.clone()).collect();
+    let mut refused = None;
+
+    for (block, old) in read(&file) {
+        if produced.contains(&old) || fs::symlink_metadata(&old).is_err() {
+            continue;
+        }
+        if !run.accounts(&block) {
+            entries.insert((block, old));
+            continue;
+        }
+        let old = &old;
+        // Entities inside, or holding, something deployed this time are not orphans
+        if produced.iter().any(|p| p.starts_with(old) || old.starts_with(p)) {
+            continue;
+        }
+
+        if !opt.PRUNE {
+            println!("[ Orphan] {:?}", old);
+            entries.insert((block, old.clone()));
+            continue;
+        }
+
+        // Pruning is removal like any other, so asks and refuses as it does
+        // An orphan left in place stays in the manifest to be pruned later
+        match deploy::remove(old, opt, run) {
+            Ok(deploy::Outcome::REMOVED) => {
+                if !opt.PLAN {
+                    log.report("pruned", old);
+                }
+            },
+            Ok(outcome) => {
+                log.report(outcome.name(), old);
+                entries.insert((block, old.clone()));
+            },
+            Err(err) => {
+                println!("[ Orphan] {:?}", old);
+                entries.insert((block, old.clone()));
+                refused = refused.or(Some(err));
+            },
+        }
+    }
+
+    if !opt.PLAN {
+        write(&file, &entries)?;
+    }
+    match refused {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
@@ -1,5 +1,6 @@
 use std::str;
 use std::fmt;
+use std::cmp;
 use util;
 
 // A span represents a slice into the input string
@@ -37,6 +38,21 @@ impl<'src> Span<'src> {
     pub fn slice(&self) -> &'src str {
        str::from_utf8(&self.src.as_bytes()[self.lptr..(self.rptr + 1)]).unwrap()
     }
+
+    // line - The line on which a span begins, counting from one
+    pub fn line(&self) -> usize {
+        self.src[..self.lptr].matches('\n').count() + 1
+    }
+
+    // col - The offset at which a span begins within its line
+    pub fn col(&self) -> usize {
+        self.lptr - self.src[..self.lptr].rfind('\n').map(|n| n + 1).unwrap_or(0)
+    }
+
+    // width - The extent of a span on the line it begins on, at least one
+    pub fn width(&self) -> usize {
+        cmp::max(1, self.slice().split('\n').next().unwrap_or("").len())
+    }
 }
 
 impl<'src> fmt::Debug for Span<'src> {
@@ -59,17 +75,24 @@ pub enum TokenType {
     EQUALS,   // =
     DARROW,   // =>
     AARROW,   // >>
+    TARROW,   // ~>
     COMMA ,   // ,
     PIPE  ,   // |
     COLON ,   // :
     HASH  ,   // #
+    BANG  ,   // !
+    PLUSEQ,   // +=
+    MINUSEQ,  // -=
+    TILDEEQ,  // ~=
+    COLONEQ,  // :=
+    LARROW,   // <=
     ERR   ,
 }
 
 #[derive(Copy, Clone)]
 pub struct Token<'src> {
     id: usize,
-    pub lno: usize,
+    pub lno: usize,  // The line on which the token's statement began
     pub tt: TokenType,
     pub val: Span<'src>,
 }
@@ -89,13 +112,14 @@ enum Within {
     ARROW   ,
     DARROW  ,
     AARROW  ,
+    TARROW  ,
 }
 
 fn breaking(c: char) -> bool {
      if c.is_alphanumeric() {
          return false;
      }
- 
+
      if c.is_whitespace() {
          return true;
      }
@@ -103,28 +127,140 @@ fn breaking(c: char) -> bool {
      ['{','}','[',']','$','@','-','>','=',',','!','|','#'].iter().find(|b| **b == c).is_some()
 }
 
-/* tokenise() takes input strings and processes them into strings of tokens */
+// index_at - Find the index of the character at (or else after) a byte offset
+fn index_at(ci: &[(usize, char)], ptr: usize) -> usize {
+    ci.binary_search_by_key(&ptr, |&(i, _)| i).unwrap_or_else(|n| n)
+}
 
-pub fn tokenise<'src>(log: &mut util::Log, lno: usize, init_id: &mut usize, input: &'src str) -> Vec<Token<'src>> {
+// line_end - Find the index of the newline ending the line an index is on
+fn line_end(ci: &[(usize, char)], idx: usize) -> usize {
+    ci.iter().skip(idx).position(|&(_, c)| c == '\n').map(|n| idx + n).unwrap_or(ci.len())
+}
 
-    if input.trim_start().starts_with("//") {
-        return vec![];
+// heredoc - Find the body of a heredoc which begins at a given offset,
+// along with the offset just past the line which terminates it
+// The body is kept exactly as written, indentation and all
+fn heredoc<'src>(input: &'src str, start: usize, term: &str) -> Option<(Span<'src>, usize)> {
+    let mut at = start;
+    while at < input.len() {
+        let end = input[at..].find('\n').map(|n| at + n).unwrap_or(input.len());
+        if input[at..end].trim() == term {
+            // The newline before the terminator belongs to neither
+            let mut body = Span::begin(input, start);
+            body.conclude_prev(cmp::max(at - 1, start));
+            return Some((body, cmp::min(end + 1, input.len())));
+        }
+        at = end + 1;
     }
+    None
+}
+
+// normalise - Prepare the source of a Jannfile for tokenising
+// Jannfiles written on Windows end their lines with \r\n, which is read
+// as a plain \n throughout, continuations and heredocs included
+pub fn normalise(source: &str) -> String {
+    source.replace("\r\n", "\n")
+}
 
-    let mut id = *init_id;
+/* tokenise() takes a whole input string and processes it into a string of tokens
+   Each token carries the line on which its statement began, so that a line
+   continued with a trailing backslash reads as one */
+
+pub fn tokenise<'src>(log: &mut util::Log, input: &'src str) -> Vec<Token<'src>> {
+    let mut id: usize = 1;
+    let mut lno: usize = 1;
+    let mut counted: usize = 0;
     let mut within: Within = Within::NONE;
     let mut esc: bool = false;
     let mut span: Span = Span::single(input, 0);
-    let mut toks = vec![];
+    let mut toks: Vec<Token> = vec![];
+
+    // Heredocs opened on the current line, by token index and terminator
+    let mut heredocs: Vec<(usize, &str)> = vec![];
 
     let ci : Vec<(usize, char)> = input.char_indices().collect();
-    let inlen = ci.len();
     let mut idx: usize = 0;
 
     'tok: while let Some((i, c)) = ci.get(idx) {
         let i = *i; let c = *c;
+        let next = ci.get(idx + 1).map(|&(_, n)| n);
+
+        // A backslash ending a line continues it on the next. Quoted strings
+        // and commands keep the backslash, and interpolation joins the lines
+        if c == '\\' && next == Some('\n') {
+            match within {
+                Within::NONE | Within::COMSTART => {
+                    idx += 2;
+                    continue 'tok;
+                },
+                Within::BSTRING if !esc => {
+                    span.conclude_prev(i);
+                    toks.push(Token { id, lno, tt: TokenType::STRING, val: span } );
+                    id += 1; span = Span::single(input, 0);
+                    within = Within::NONE;
+                    idx += 2;
+                    continue 'tok;
+                },
+                _ => {},
+            }
+        }
+
+        // Heredocs open with '<<' and the word which will end them. Their
+        // bodies are the lines after this one, so the rest of it is read first
+        let heredoc_tt = match within {
+            Within::NONE     => Some(TokenType::STRING),
+            Within::COMSTART => Some(TokenType::COMMAND),
+            _ => None,
+        };
+        if let (Some(tt), '<', Some('<')) = (heredoc_tt, c, next) {
+            let term = &input[i + 2..];
+            let term = &term[..term.find(|t: char| !(t.is_alphanumeric() || t == '_')).unwrap_or(term.len())];
+            span = Span::begin(input, i);
+            span.conclude(i + 1 + term.len());
+            within = Within::NONE;
+            if term.is_empty() {
+                toks.push(Token { id, lno, tt: TokenType::ERR, val: span } );
+                log.error("Heredoc Without Terminator", "Name the word which ends it, as in <<EOF", &toks.last().unwrap());
+                id += 1;
+                idx = line_end(&ci, idx);
+                continue 'tok;
+            }
+            heredocs.push((toks.len(), term));
+            toks.push(Token { id, lno, tt, val: span } );
+            id += 1; span = Span::single(input, 0);
+            idx = index_at(&ci, i + 2 + term.len());
+            continue 'tok;
+        }
+
         match within {
             Within::NONE    => {
+                if c == '\n' {
+                    let mut at = i + 1;
+                    for (tok, term) in heredocs.drain(..) {
+                        match heredoc(input, at, term) {
+                            Some((body, end)) => {
+                                toks[tok].val = body;
+                                at = end;
+                            },
+                            None => {
+                                log.error("Unterminated Heredoc",
+                                          &format!("End it with a line holding only {}", term), &toks[tok]);
+                                return toks;
+                            },
+                        }
+                    }
+                    lno += input[counted..at].matches('\n').count();
+                    counted = at;
+                    idx = index_at(&ci, at);
+                    continue 'tok;
+                }
+
+                // Comments take up the whole of a line
+                if c == '/' && next == Some('/') && input[..i].rsplit('\n').next().unwrap_or("").trim().is_empty() {
+                    idx = line_end(&ci, idx);
+                    continue 'tok;
+                }
+
                 let stt = match c {
                     '{' => Some(TokenType::LBRACE),
                     '}' => Some(TokenType::RBRACE),
@@ -135,14 +271,33 @@ pub fn tokenise<'src>(log: &mut util::Log, lno: usize, init_id: &mut usize, inpu
                     '|' => Some(TokenType::PIPE  ),
                     ':' => Some(TokenType::COLON ),
                     '#' => Some(TokenType::HASH  ),
-                    _   => None, 
+                    '!' => Some(TokenType::BANG  ),
+                    _   => None,
                 };
 
-                if let Some(stt) = stt {
+                // Edit operators, and the write arrow, are a single character followed by '='
+                let ett = match (c, next) {
+                    ('+', Some('=')) => Some(TokenType::PLUSEQ ),
+                    ('-', Some('=')) => Some(TokenType::MINUSEQ),
+                    ('~', Some('=')) => Some(TokenType::TILDEEQ),
+                    (':', Some('=')) => Some(TokenType::COLONEQ),
+                    ('<', Some('=')) => Some(TokenType::LARROW ),
+                    _ => None,
+                };
+
+                if let Some(ett) = ett {
+                    span = Span::begin(input, i);
+                    span.conclude(i + 1);
+                    toks.push(Token { id, lno, tt: ett, val: span } );
+                    id += 1; span = Span::single(input, 0);
+                    idx += 2;
+                    continue 'tok;
+                }
+                else if let Some(stt) = stt {
                     toks.push(Token { id, lno, tt: stt, val: Span::single(input, i) } );
                     id += 1
                 }
-                else {  
+                else {
                     span = Span::begin(input, i);
                     within = match c {
                         '-' => Within::ARROW,
@@ -150,12 +305,14 @@ pub fn tokenise<'src>(log: &mut util::Log, lno: usize, init_id: &mut usize, inpu
                         '>' => Within::AARROW,
                         '"' => Within::QSTRING,
                         '$' => Within::COMSTART,
+                        '~' if next == Some('>') => Within::TARROW,
                         c if !breaking(c) => Within::BSTRING,
                         _   => Within::NONE,
                     }
                 }
             },
 
+            // Quoted strings may run over several lines
             Within::QSTRING => {
                 if c == '"' && !esc {
                     span.conclude(i);
@@ -167,7 +324,12 @@ pub fn tokenise<'src>(log: &mut util::Log, lno: usize, init_id: &mut usize, inpu
             },
 
             Within::BSTRING => {
-                if breaking(c) && !esc {
+                // A hyphen only ends a bare string when it begins an arrow,
+                // so that names like foo-bar.conf need no quotes, and likewise
+                // for the characters which begin edit operators
+                let arrow = (c == '~' || c == '-') && next == Some('>');
+                let operator = (c == '+' || c == '-' || c == '~' || c == ':' || c == '<') && next == Some('=');
+                if ((breaking(c) && c != '-') || arrow || operator) && !esc {
                     span.conclude_prev(i);
                     toks.push(Token { id, lno, tt: TokenType::STRING, val: span } );
                     id += 1; span = Span::single(input, 0);
@@ -184,30 +346,50 @@ pub fn tokenise<'src>(log: &mut util::Log, lno: usize, init_id: &mut usize, inpu
             },
 
             Within::COMSTART => {
+                if c == '\n' {
+                    span.conclude_prev(i);
+                    toks.push(Token { id, lno, tt: TokenType::ERR, val: span } );
+                    log.error("Empty Command", "Add a command after the '$'", &toks.last().unwrap());
+                    id += 1; span = Span::single(input, 0);
+                    within = Within::NONE;
+                    continue 'tok;
+                }
                 if !c.is_whitespace() {
                     span = Span::begin(input, i);
                     within = Within::COMMAND;
+                    esc = false;
                     continue 'tok;
                 }
             },
 
             Within::COMMAND => {
-                // Within a command
+                // A command runs to the end of its line, or of the last
+                // line it is continued onto
+                if c == '\n' && !esc {
+                    span.conclude_prev(i);
+                    toks.push(Token { id, lno, tt: TokenType::COMMAND, val: span } );
+                    id += 1; span = Span::single(input, 0);
+                    within = Within::NONE;
+                    continue 'tok;
+                }
+                esc = c == '\\' && !esc;
             },
 
-           arr @ Within::ARROW | arr @ Within::AARROW  => {
+           arr @ Within::ARROW | arr @ Within::AARROW | arr @ Within::TARROW => {
                 if c == '>' {
                     span.conclude(i);
-                    toks.push(Token { id, lno, tt: match arr { Within::ARROW => TokenType::ARROW, Within::AARROW => TokenType::AARROW, _ => unreachable!() }, val: span } );
-                    id += 1; span = Span::single(input, 0); 
+                    toks.push(Token { id, lno, tt: match arr { Within::ARROW => TokenType::ARROW, Within::AARROW => TokenType::AARROW, Within::TARROW => TokenType::TARROW, _ => unreachable!() }, val: span } );
+                    id += 1; span = Span::single(input, 0);
                     within = Within::NONE;
                 }
                 else {
                     span.conclude(i - 1);
                     toks.push(Token { id, lno, tt: TokenType::ERR, val: span } );
                     log.error("Headless Arrow", "Add a '>' character", &toks.last().unwrap());
-                    *init_id = id + 1;
-                    return toks;
+                    id += 1; span = Span::single(input, 0);
+                    within = Within::NONE;
+                    idx = line_end(&ci, idx);
+                    continue 'tok;
                 }
             },
 
@@ -218,6 +400,14 @@ pub fn tokenise<'src>(log: &mut util::Log, lno: usize, init_id: &mut usize, inpu
                     id += 1; span = Span::single(input, 0);
                     within = Within::NONE;
                 }
+                else if c == '\n' {
+                    span.conclude(i - 1);
+                    toks.push(Token { id, lno, tt: TokenType::ERR, val: span } );
+                    log.error("Unexpected End of Line", "Close this construct", &toks.last().unwrap());
+                    id += 1; span = Span::single(input, 0);
+                    within = Within::NONE;
+                    continue 'tok;
+                }
                 else {
                     span.conclude(i - 1);
                     toks.push(Token { id, lno, tt: TokenType::EQUALS, val: span } );
@@ -228,32 +418,30 @@ pub fn tokenise<'src>(log: &mut util::Log, lno: usize, init_id: &mut usize, inpu
             },
         }
         idx += 1;
+    }
 
-        if idx >= inlen {
-            match within {
-                Within::NONE => {},
-                Within::BSTRING => {
-                    span.conclude(i);
-                    toks.push(Token { id, lno, tt: TokenType::STRING, val: span } );
-                    id += 1;
-                },
-                Within::COMMAND => {
-                    span.conclude(i);
-                    toks.push(Token { id, lno, tt: TokenType::COMMAND, val: span } );
-                    id += 1;
-                },
-                _ => {
-                    span.conclude(i);
-                    toks.push(Token { id, lno, tt: TokenType::ERR, val: span } );
-                    log.error("Unexpected EOF", "Close this construct", &toks.last().unwrap());
-                    *init_id = id + 1;
-                    return toks;
-                }
-            }
-            break;
+    // Whatever is still open at the end of the input must conclude there
+    let last = input.len().saturating_sub(1);
+    match within {
+        Within::NONE => {},
+        Within::BSTRING => {
+            span.conclude(last);
+            toks.push(Token { id, lno, tt: TokenType::STRING, val: span } );
+        },
+        Within::COMMAND => {
+            span.conclude(last);
+            toks.push(Token { id, lno, tt: TokenType::COMMAND, val: span } );
+        },
+        _ => {
+            span.conclude(last);
+            toks.push(Token { id, lno, tt: TokenType::ERR, val: span } );
+            log.error("Unexpected EOF", "Close this construct", &toks.last().unwrap());
+            return toks;
         }
     }
-    *init_id = id;
+    if let Some(&(tok, term)) = heredocs.first() {
+        log.error("Unterminated Heredoc", &format!("End it with a line holding only {}", term), &toks[tok]);
+    }
     toks
 }
 
@@ -271,8 +459,15 @@ pub enum PTNodeType {
     JNAME  ,   // @connaught
     NAME   ,   // Any variable or string
     LIST   ,   // [foo, bar, baz]
-    INSERT ,   // src => dst
-    COPY   ,   // src >> dst
+    INSERT ,   // src => dst [modifiers]
+    COPY   ,   // src >> dst [modifiers]
+    LINK   ,   // src ~> dst [modifiers]
+    PRESENT,   // dst += line
+    ABSENT ,   // dst -= line
+    SUBST  ,   // dst ~= [pattern, replacement]
+    SETKEY ,   // dst := [key, value] [modifiers]
+    REMOVE ,   // ! dst
+    WRITE  ,   // dst <= content [modifiers]
     PIPELINE,  // pl | stage1 : stage2 | stage3
     FLAG    ,  // 'Virtual node', denotes if stage is enabled
 }
@@ -530,6 +725,71 @@ fn parse_block(parser: &mut Parser, tag: usize) -> Option<usize> {
     Some(block)
 }
 
+// parse_modifiers - Parse an optional list of modifiers, such as [merge]
+// The list must open on the same line as the statement it modifies,
+// otherwise it would be indistinguishable from the start of a map
+// Modifiers which take a value are written as assignments, [exclude = "*.swp"]
+
+fn parse_modifiers(parser: &mut Parser, stmt: usize, lno: usize) -> Option<()> {
+    if !parser.has_cur() || parser.tok().lno != lno {
+        return Some(());
+    }
+    if let TokenType::LBRACK = parser.tok().tt {} else {
+        return Some(());
+    }
+
+    let mods = parser.orphan(PTNodeType::LIST, parser.tok_id());
+    parser.step_or_err("Bare Left Bracket", "Cannot conclude here")?;
+    loop {
+        match parser.tok().tt {
+            TokenType::RBRACK => {
+                parser.step();
+                break;
+            },
+            _ => {
+                let elem = parse_val(parser)?;
+                if !parser.has_cur() {
+                    parser.retreat();
+                    parser.error("Unclosed Modifiers", "Add a right bracket after here");
+                    return None;
+                }
+                if let TokenType::EQUALS = parser.tok().tt {
+                    let assign = parser.orphan(PTNodeType::ASSIGN, parser.tok_id());
+                    parser.tree.bind_child(assign, elem);
+                    parser.step_or_err("Bare Equals", "Cannot conclude here")?;
+                    let val = parse_val(parser)?;
+                    parser.tree.bind_child(assign, val);
+                    parser.tree.bind_child(mods, assign);
+                    if !parser.has_cur() {
+                        parser.retreat();
+                        parser.error("Unclosed Modifiers", "Add a right bracket after here");
+                        return None;
+                    }
+                }
+                else {
+                    parser.tree.bind_child(mods, elem);
+                }
+            },
+        }
+
+        match parser.tok().tt {
+            TokenType::COMMA => {
+                parser.step_or_err("Bare Comma", "Cannot conclude here")?;
+            },
+            TokenType::RBRACK => {
+                parser.step();
+                break;
+            }
+            _ => {
+                parser.error("Malformed Modifiers", "Add a comma or bracket before here");
+                return None;
+            },
+        }
+    }
+    parser.tree.bind_child(stmt, mods);
+    Some(())
+}
+
 // parse_val_stmt - Parse statement with the structure <val> <operator> ...
 
 fn parse_val_stmt(parser: &mut Parser) -> Option<usize> {
@@ -554,19 +814,58 @@ fn parse_val_stmt(parser: &mut Parser) -> Option<usize> {
             Some(stmt)
         },
         TokenType::AARROW => {
+            let lno = parser.tok().lno;
             let stmt = parser.orphan(PTNodeType::COPY, tok_id);
             parser.tree.bind_child(stmt, val);
             parser.step_or_err("Bare Copy Arrow", "Cannot conclude here")?;
             let rval = parse_val(parser)?;
             parser.tree.bind_child(stmt, rval);
+            parse_modifiers(parser, stmt, lno)?;
             Some(stmt)
         },
         TokenType::DARROW => {
+            let lno = parser.tok().lno;
             let stmt = parser.orphan(PTNodeType::INSERT, tok_id);
             parser.tree.bind_child(stmt, val);
             parser.step_or_err("Bare Insertion Arrow", "Cannot conclude here")?;
             let rval = parse_val(parser)?;
             parser.tree.bind_child(stmt, rval);
+            parse_modifiers(parser, stmt, lno)?;
+            Some(stmt)
+        },
+        TokenType::TARROW => {
+            let lno = parser.tok().lno;
+            let stmt = parser.orphan(PTNodeType::LINK, tok_id);
+            parser.tree.bind_child(stmt, val);
+            parser.step_or_err("Bare Link Arrow", "Cannot conclude here")?;
+            let rval = parse_val(parser)?;
+            parser.tree.bind_child(stmt, rval);
+            parse_modifiers(parser, stmt, lno)?;
+            Some(stmt)
+        },
+        TokenType::PLUSEQ | TokenType::MINUSEQ | TokenType::TILDEEQ | TokenType::COLONEQ => {
+            let lno = parser.tok().lno;
+            let nt = match cur_tt { TokenType::PLUSEQ  => PTNodeType::PRESENT,
+                                    TokenType::MINUSEQ => PTNodeType::ABSENT,
+                                    TokenType::TILDEEQ => PTNodeType::SUBST,
+                                    TokenType::COLONEQ => PTNodeType::SETKEY,
+                                    _ => unreachable!() };
+            let stmt = parser.orphan(nt, tok_id);
+            parser.tree.bind_child(stmt, val);
+            parser.step_or_err("Bare Edit Operator", "Cannot conclude here")?;
+            let rval = parse_val(parser)?;
+            parser.tree.bind_child(stmt, rval);
+            parse_modifiers(parser, stmt, lno)?;
+            Some(stmt)
+        },
+        TokenType::LARROW => {
+            let lno = parser.tok().lno;
+            let stmt = parser.orphan(PTNodeType::WRITE, tok_id);
+            parser.tree.bind_child(stmt, val);
+            parser.step_or_err("Bare Write Arrow", "Cannot conclude here")?;
+            let rval = parse_val(parser)?;
+            parser.tree.bind_child(stmt, rval);
+            parse_modifiers(parser, stmt, lno)?;
             Some(stmt)
         },
         TokenType::PIPE | TokenType::COLON => {
@@ -677,6 +976,13 @@ fn parse_stmt(parser: &mut Parser) -> Option<usize> {
             parser.tree.bind_child(stmt, data);
             Some(stmt)
         }
+        TokenType::BANG => {
+            let stmt = parser.orphan(PTNodeType::REMOVE, tok_id);
+            parser.step_or_err("Bare '!'", "Add a path to remove after here")?;
+            let path = parse_val(parser)?;
+            parser.tree.bind_child(stmt, path);
+            Some(stmt)
+        },
         TokenType::RBRACE  => { parser.step(); Some(0) },
         _                  => { parse_val_stmt(parser) },
     }
@@ -702,3 +1008,92 @@ pub fn parse<'log, 'src: 'log>(log: &'log mut util::Log<'src>, toks: &'src Vec<T
     parse_file(&mut parser);
     parser.tree
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // toks - Tokenise a source, describing each token by type, text and line
+    fn toks(source: &str) -> (Vec<String>, bool) {
+        let lines: Vec<String> = source.lines().map(String::from).collect();
+        let mut log = util::Log::new(String::from("test"), &lines);
+        let toks = tokenise(&mut log, source);
+        (toks.iter().map(|t| format!("{:?} {:?} {}", t.tt, t.val.slice(), t.lno)).collect(), log.has_err())
+    }
+
+    fn ok(source: &str) -> Vec<String> {
+        let (toks, err) = toks(source);
+        assert!(!err, "{:?} failed to tokenise", source);
+        toks
+    }
+
+    #[test]
+    fn commands_keep_their_continuations() {
+        assert_eq!(ok("blk {\n    $ echo one \\\n      two\n    f >> /etc/f\n}\n"),
+                   vec!["STRING \"blk\" 1", "LBRACE \"{\" 1",
+                        "COMMAND \"echo one \\\\\\n      two\" 2",
+                        "STRING \"f\" 4", "AARROW \">>\" 4", "STRING \"/etc/f\" 4",
+                        "RBRACE \"}\" 5"]);
+    }
+
+    #[test]
+    fn continued_statements_read_as_one_line() {
+        assert_eq!(ok("a >> \\\n  b\nc\n"),
+                   vec!["STRING \"a\" 1", "AARROW \">>\" 1", "STRING \"b\" 1", "STRING \"c\" 3"]);
+    }
+
+    #[test]
+    fn quoted_strings_run_over_lines() {
+        assert_eq!(ok("a = \"multi\nline\" \\\n  [x]\nb = c\n"),
+                   vec!["STRING \"a\" 1", "EQUALS \"=\" 1", "STRING \"multi\\nline\" 1",
+                        "LBRACK \"[\" 1", "STRING \"x\" 1", "RBRACK \"]\" 1",
+                        "STRING \"b\" 4", "EQUALS \"=\" 4", "STRING \"c\" 4"]);
+    }
+
+    #[test]
+    fn heredoc_followed_by_a_statement() {
+        assert_eq!(ok("/etc/f <= <<EOF [mode = 0644]\n  body\n\n  more\nEOF\n$ next\n"),
+                   vec!["STRING \"/etc/f\" 1", "LARROW \"<=\" 1", "STRING \"  body\\n\\n  more\" 1",
+                        "LBRACK \"[\" 1", "STRING \"mode\" 1", "EQUALS \"=\" 1", "STRING \"0644\" 1",
+                        "RBRACK \"]\" 1", "COMMAND \"next\" 6"]);
+    }
+
+    #[test]
+    fn heredoc_as_a_command() {
+        assert_eq!(ok("$ <<SH\nfor x in a b; do\n  echo $x\ndone\n  SH\nc\n"),
+                   vec!["COMMAND \"for x in a b; do\\n  echo $x\\ndone\" 1", "STRING \"c\" 6"]);
+    }
+
+    #[test]
+    fn heredoc_may_be_empty() {
+        assert_eq!(ok("f <= <<EOF\nEOF\ng\n"),
+                   vec!["STRING \"f\" 1", "LARROW \"<=\" 1", "STRING \"\" 1", "STRING \"g\" 3"]);
+    }
+
+    #[test]
+    fn unterminated_heredoc_is_an_error() {
+        assert!(toks("/etc/f <= <<EOF\nx\n").1);
+        assert!(toks("/etc/f <= <<\nx\n").1);
+    }
+
+    #[test]
+    fn empty_command_is_an_error() {
+        assert!(toks("$\nx\n").1);
+    }
+
+    #[test]
+    fn crlf_reads_as_newlines() {
+        let source = normalise("$ echo one \\\r\n  two\r\na \\\r\n b\r\nf <= <<EOF\r\nx\r\nEOF\r\n");
+        assert_eq!(ok(&source),
+                   vec!["COMMAND \"echo one \\\\\\n  two\" 1", "STRING \"a\" 3", "STRING \"b\" 3",
+                        "STRING \"f\" 5", "LARROW \"<=\" 5", "STRING \"x\" 5"]);
+    }
+
+    #[test]
+    fn names_operators_and_comments() {
+        assert_eq!(ok("foo-bar.conf -> x\nk += v\n// c\n   // d\nl ~> m\n"),
+                   vec!["STRING \"foo-bar.conf\" 1", "ARROW \"->\" 1", "STRING \"x\" 1",
+                        "STRING \"k\" 2", "PLUSEQ \"+=\" 2", "STRING \"v\" 2",
+                        "STRING \"l\" 5", "TARROW \"~>\" 5", "STRING \"m\" 5"]);
+    }
+}
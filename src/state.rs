@@ -0,0 +1,372 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::ffi::OsStr;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf, Component};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use deploy::{self, Attrs, DeployError};
+use util;
+
+// Each run keeps its state in <state>/backups/<run-id>/
+// Overwritten entities are moved in under a sequence number, and the
+// journal records every mutation made to the filesystem in order.
+// Entries are written before the mutation they describe is attempted,
+// so that a run which dies halfway through can still be undone.
+
+fn backup_root() -> Result<PathBuf, DeployError> {
+    match util::state_dir() {
+        Some(dir) => Ok(dir.join("backups")),
+        None => Err(DeployError::locked("Backup", "Could not find a state directory")),
+    }
+}
+
+#[derive(Debug)]
+enum Entry {
+    MKDIR(PathBuf),          // An intermediate directory was created
+    CREATE(PathBuf),         // A file or tree was copied into place
+    BACKUP(String, PathBuf), // An entity was moved into a backup slot
+    SWAP(PathBuf, PathBuf),  // Two entities were exchanged
+    ATTRS(Attrs, PathBuf),   // An entity's attributes, before they were changed
+    SUDO(PathBuf),           // A Jannfile was included with sudo, journalling elsewhere
+}
+
+impl Entry {
+    fn encode(&self) -> Vec<u8> {
+        let (mut record, path) = match *self {
+            Entry::MKDIR(ref path) => (b"mkdir\t".to_vec(), path),
+            Entry::CREATE(ref path) => (b"create\t".to_vec(), path),
+            Entry::SUDO(ref path) => (b"sudo\t".to_vec(), path),
+            Entry::BACKUP(ref slot, ref path) => (format!("backup\t{}\t", slot).into_bytes(), path),
+            Entry::ATTRS(ref old, ref path) => {
+                let field = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_default();
+                (format!("attrs\t{}:{}:{}\t", old.mode.map(|m| format!("{:o}", m)).unwrap_or_default(),
+                         field(old.uid), field(old.gid)).into_bytes(), path)
+            },
+            Entry::SWAP(ref a, ref b) => {
+                let mut record = b"swap\t".to_vec();
+                record.extend_from_slice(a.as_os_str().as_bytes());
+                record.push(b'\t');
+                (record, b)
+            },
+        };
+        record.extend_from_slice(path.as_os_str().as_bytes());
+        record.push(b'\n');
+        record
+    }
+
+    fn decode(record: &[u8]) -> Option<Entry> {
+        let mut fields = record.splitn(3, |b| *b == b'\t');
+        let path = |f: &[u8]| PathBuf::from(OsStr::from_bytes(f));
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(b"mkdir"), Some(p), None) => Some(Entry::MKDIR(path(p))),
+            (Some(b"create"), Some(p), None) => Some(Entry::CREATE(path(p))),
+            (Some(b"sudo"), Some(p), None) => Some(Entry::SUDO(path(p))),
+            (Some(b"backup"), Some(s), Some(p)) => {
+                Some(Entry::BACKUP(String::from_utf8_lossy(s).to_string(), path(p)))
+            },
+            (Some(b"swap"), Some(a), Some(b)) => Some(Entry::SWAP(path(a), path(b))),
+            (Some(b"attrs"), Some(a), Some(p)) => {
+                let a = String::from_utf8_lossy(a).to_string();
+                let mut fields = a.split(':');
+                let mut next = |radix| fields.next().and_then(|f| u32::from_str_radix(f, radix).ok());
+                let old = Attrs { mode: next(8), uid: next(10), gid: next(10) };
+                Some(Entry::ATTRS(old, path(p)))
+            },
+            _ => None,
+        }
+    }
+}
+
+// A Run represents a single invocation of jann, along with any included
+// Jannfiles, all of which share one run id
+pub struct Run {
+    pub id: String,
+    pub drifted: bool,
+    pub journal: bool,
+    count: usize,
+    announced: bool,
+    all: bool,
+    produced: Vec<(String, PathBuf)>,
+    block: String,
+    blocks: BTreeSet<String>,
+    ran: BTreeSet<String>,
+}
+
+impl Run {
+    pub fn new(id: Option<String>) -> Run {
+        let id = id.unwrap_or_else(|| {
+            let secs = SystemTime::now().duration_since(UNIX_EPOCH)
+                                        .map(|d| d.as_secs())
+                                        .unwrap_or(0);
+            format!("{}-{}", secs, process::id())
+        });
+        Run { id, drifted: false, journal: true, count: 0, announced: false, all: false,
+              produced: vec![], block: String::new(), blocks: BTreeSet::new(), ran: BTreeSet::new() }
+    }
+
+    fn dir(&mut self) -> Result<PathBuf, DeployError> {
+        let dir = backup_root()?.join(&self.id);
+        fs::create_dir_all(&dir)?;
+        if !self.announced {
+            println!("[Journal] run {}", self.id);
+            self.announced = true;
+        }
+        Ok(dir)
+    }
+
+    fn record(&mut self, entry: &Entry) -> Result<(), DeployError> {
+        if !self.journal {
+            return Ok(());
+        }
+        let dir = self.dir()?;
+        let mut journal = fs::OpenOptions::new().create(true).append(true).open(dir.join("journal"))?;
+        journal.write_all(&entry.encode())?;
+        journal.sync_data()?;
+        Ok(())
+    }
+
+    // mkdir - Create a single directory
+    pub fn mkdir(&mut self, path: &Path) -> Result<(), DeployError> {
+        self.record(&Entry::MKDIR(path.to_path_buf()))?;
+        fs::create_dir(path)?;
+        Ok(())
+    }
+
+    // create - Note that a file or tree is about to be copied into place
+    pub fn create(&mut self, path: &Path) -> Result<(), DeployError> {
+        self.record(&Entry::CREATE(path.to_path_buf()))
+    }
+
+    // swap - Exchange two entities
+    pub fn swap(&mut self, a: &Path, b: &Path) -> Result<(), DeployError> {
+        self.record(&Entry::SWAP(a.to_path_buf(), b.to_path_buf()))?;
+        deploy::swap(a, b)
+    }
+
+    // attrs - Change the attributes of an entity in place
+    pub fn attrs(&mut self, path: &Path, attrs: &Attrs) -> Result<(), DeployError> {
+        self.record(&Entry::ATTRS(Attrs::of(path)?, path.to_path_buf()))?;
+        attrs.apply(path)
+    }
+
+    // backup - Move an entity out of the way, recording where it belongs
+    pub fn backup(&mut self, path: &Path, origin: &Path) -> Result<(), DeployError> {
+        let dir = self.dir()?;
+
+        // Included Jannfiles share our directory, so skip past their slots
+        let mut slot = dir.join(self.count.to_string());
+        while fs::symlink_metadata(&slot).is_ok() {
+            self.count += 1;
+            slot = dir.join(self.count.to_string());
+        }
+
+        self.record(&Entry::BACKUP(self.count.to_string(), origin.to_path_buf()))?;
+        deploy::relocate(path, &slot)?;
+
+        self.count += 1;
+        println!("[ Backup] {:?} (run {})", origin, self.id);
+        Ok(())
+    }
+
+    // elevate - Note that a Jannfile is about to be included with sudo, whose
+    // part of the run is journalled in root's state directory instead
+    pub fn elevate(&mut self, jannfile: &Path) -> Result<(), DeployError> {
+        self.record(&Entry::SUDO(jannfile.to_path_buf()))
+    }
+
+    // produce - Note a destination that this run deployed, by the block deploying it
+    pub fn produce(&mut self, path: &Path) {
+        self.produced.push((self.block.clone(), path.to_path_buf()));
+    }
+
+    pub fn produced(&self) -> &[(String, PathBuf)] {
+        &self.produced
+    }
+
+    // define - Note a block that the Jannfile defines
+    pub fn define(&mut self, block: &str) {
+        self.blocks.insert(block.to_string());
+    }
+
+    // enter - Note that a block of the Jannfile is about to run
+    pub fn enter(&mut self, block: &str) {
+        self.block = block.to_string();
+        self.ran.insert(block.to_string());
+    }
+
+    // accounts - Determine whether this run speaks for what a block deploys,
+    // which it cannot for a block still in the Jannfile that has not run
+    pub fn accounts(&self, block: &str) -> bool {
+        self.ran.contains(block) || !self.blocks.contains(block)
+    }
+
+    // confirm - Ask on the terminal whether an overwrite may go ahead
+    // Answering 'all' lets every later overwrite in this run through
+    pub fn confirm(&mut self, path: &Path, what: &str) -> Result<bool, DeployError> {
+        if self.all {
+            return Ok(true);
+        }
+        let tty_err = |_| DeployError::locked("Confirm", "Interactive mode needs a terminal");
+        let mut tty_out = fs::OpenOptions::new().write(true).open("/dev/tty").map_err(tty_err)?;
+        let mut tty_in = io::BufReader::new(fs::File::open("/dev/tty").map_err(tty_err)?);
+        loop {
+            write!(tty_out, "[    Ask] Overwrite {:?} ({})? [y]es/[n]o/[a]ll/[q]uit ", path, what)?;
+            tty_out.flush()?;
+            let mut answer = String::new();
+            if tty_in.read_line(&mut answer)? == 0 {
+                answer = String::from("q");
+            }
+            match answer.trim().to_lowercase().as_ref() {
+                "y" | "yes" => { return Ok(true); },
+                "n" | "no" => { return Ok(false); },
+                "a" | "all" => {
+                    self.all = true;
+                    return Ok(true);
+                },
+                "q" | "quit" => {
+                    return Err(DeployError::locked("Confirm", "Deployment abandoned"));
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+fn run_dir(id: Option<&str>) -> Result<PathBuf, DeployError> {
+    let root = backup_root()?;
+    let id = match id {
+        Some(id) => id.to_string(),
+        None => {
+            // Without an id we take the run with the most recently written journal
+            let mut latest = None;
+            if let Ok(runs) = fs::read_dir(&root) {
+                for run in runs.filter_map(|r| r.ok()) {
+                    if let Ok(modified) = run.path().join("journal").metadata().and_then(|m| m.modified()) {
+                        let newer = match latest {
+                            Some((ref t, _)) => modified > *t,
+                            None => true,
+                        };
+                        if newer {
+                            latest = Some((modified, run.file_name().to_string_lossy().to_string()));
+                        }
+                    }
+                }
+            }
+            match latest {
+                Some((_, id)) => id,
+                None => { return Err(DeployError::locked("Journal", "No runs have been recorded")); },
+            }
+        },
+    };
+
+    let mut id_cmps = Path::new(&id).components();
+    match (id_cmps.next(), id_cmps.next()) {
+        (Some(Component::Normal(_)), None) => Ok(root.join(id)),
+        _ => Err(DeployError::locked("Journal", "Invalid run id")),
+    }
+}
+
+fn read_journal(dir: &Path) -> Result<Vec<Entry>, DeployError> {
+    let journal = fs::read(dir.join("journal")).map_err(|_| {
+        DeployError::locked("Journal", "No journal exists for this run")
+    })?;
+
+    let mut entries = vec![];
+    for record in journal.split(|b| *b == b'\n').filter(|r| !r.is_empty()) {
+        match Entry::decode(record) {
+            Some(entry) => entries.push(entry),
+            None => { return Err(DeployError::locked("Journal", "Journal is corrupt")); },
+        }
+    }
+    Ok(entries)
+}
+
+fn remove_entity(path: &Path) -> Result<bool, DeployError> {
+    match fs::symlink_metadata(path) {
+        Ok(ref meta) if meta.is_dir() => { fs::remove_dir_all(path)?; Ok(true) },
+        Ok(_) => { fs::remove_file(path)?; Ok(true) },
+        Err(_) => Ok(false),
+    }
+}
+
+// elevated - Point out the part of a run made by Jannfiles included with sudo,
+// which can only be undone from root's state directory
+fn elevated(dir: &Path, entry: &Entry, verb: &str) {
+    if let Entry::SUDO(ref jannfile) = *entry {
+        let id = dir.file_name().unwrap_or_default().to_string_lossy();
+        println!("[   Sudo] {:?} was included with sudo, so undo it with: sudo jann {} {}", jannfile, verb, id);
+    }
+}
+
+fn restore_slot(dir: &Path, slot: &str, path: &Path) -> Result<(), DeployError> {
+    let slot = dir.join(slot);
+    // The run may have died before the entity was moved
+    if fs::symlink_metadata(&slot).is_err() {
+        return Ok(());
+    }
+    remove_entity(path)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    deploy::relocate(&slot, path)?;
+    println!("[Restore] {:?}", path);
+    Ok(())
+}
+
+// restore - Put back everything that a run replaced, most recent first
+pub fn restore(id: &str) -> Result<(), DeployError> {
+    let dir = run_dir(Some(id))?;
+    for entry in read_journal(&dir)?.iter().rev() {
+        if let Entry::BACKUP(ref slot, ref path) = *entry {
+            restore_slot(&dir, slot, path)?;
+        }
+        elevated(&dir, entry, "restore");
+    }
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+// rollback - Undo every mutation a run made, replaying its journal in reverse
+pub fn rollback(id: Option<&str>) -> Result<(), DeployError> {
+    let dir = run_dir(id)?;
+    for entry in read_journal(&dir)?.iter().rev() {
+        match *entry {
+            Entry::CREATE(ref path) => {
+                if remove_entity(path)? {
+                    println!("[ Remove] {:?}", path);
+                }
+            },
+            Entry::MKDIR(ref path) => {
+                if path.is_dir() {
+                    if fs::remove_dir(path).is_ok() {
+                        println!("[ Remove] {:?}", path);
+                    }
+                    else {
+                        println!("[   Keep] {:?} (no longer empty)", path);
+                    }
+                }
+            },
+            Entry::BACKUP(ref slot, ref path) => {
+                restore_slot(&dir, slot, path)?;
+            },
+            Entry::ATTRS(ref old, ref path) => {
+                if fs::symlink_metadata(path).is_ok() {
+                    old.apply(path)?;
+                    println!("[Restore] {:?} (attributes)", path);
+                }
+            },
+            Entry::SWAP(ref a, ref b) => {
+                // Once the old entity has been restored there is nothing to swap back
+                if fs::symlink_metadata(a).is_ok() && fs::symlink_metadata(b).is_ok() {
+                    deploy::swap(a, b)?;
+                }
+            },
+            Entry::SUDO(_) => elevated(&dir, entry, "rollback"),
+        }
+    }
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+extern crate walkdir;
+
+use std::fs;
+use std::ffi::OsStr;
+use std::process;
+use std::path::{Path, PathBuf};
+
+use deploy::{self, DeployError};
+use inter;
+use glob;
+
+// Files with this suffix are rendered through variable interpolation as
+// they are deployed, and lose the suffix on the way
+pub const SUFFIX: &str = ".jann-tmpl";
+
+// TemplateError - A failure to render a line of a template
+pub struct TemplateError {
+    pub file: PathBuf,
+    pub lno: usize,
+    pub line: String,
+    pub message: String,
+    pub hint: String,
+}
+
+pub enum RenderError {
+    Template(TemplateError),
+    Deploy(DeployError),
+}
+
+impl From<DeployError> for RenderError {
+    fn from(err: DeployError) -> Self {
+        RenderError::Deploy(err)
+    }
+}
+
+impl From<::std::io::Error> for RenderError {
+    fn from(err: ::std::io::Error) -> Self {
+        RenderError::Deploy(DeployError::from(err))
+    }
+}
+
+impl From<walkdir::Error> for RenderError {
+    fn from(err: walkdir::Error) -> Self {
+        RenderError::Deploy(DeployError::from(err))
+    }
+}
+
+// A Rendering holds the rendered copy of a source, which is deleted
+// once it has been deployed
+pub struct Rendering {
+    dir: PathBuf,
+    pub path: PathBuf,
+}
+
+impl Drop for Rendering {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn is_template(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(SUFFIX)
+}
+
+// strip - Remove the template suffix from a path, if it has one
+pub fn strip(path: &Path) -> PathBuf {
+    match path.to_str().and_then(|name| name.strip_suffix(SUFFIX)) {
+        Some(stem) => PathBuf::from(stem),
+        None => path.to_path_buf(),
+    }
+}
+
+// contains - Determine whether a source is, or holds, any templates
+pub fn contains(src: &Path) -> bool {
+    walkdir::WalkDir::new(src).into_iter()
+                              .filter_map(|e| e.ok())
+                              .any(|e| e.file_type().is_file() && is_template(e.path()))
+}
+
+fn render_file(symbols: &inter::Symbols, src: &Path, dst: &Path, keep: deploy::Preserve) -> Result<(), RenderError> {
+    let body = fs::read_to_string(src).map_err(|_| {
+        DeployError { source: "Template".to_owned(),
+                      message: format!("Could not read {:?} as text", src) }
+    })?;
+
+    let mut out = String::new();
+    for (i, line) in body.split_inclusive('\n').enumerate() {
+        match inter::expand_template(symbols, line) {
+            Ok(expanded) => out.push_str(&expanded),
+            Err((message, hint)) => {
+                return Err(RenderError::Template(TemplateError {
+                    file: src.to_path_buf(),
+                    lno: i + 1,
+                    line: line.trim_end_matches('\n').to_string(),
+                    message,
+                    hint,
+                }));
+            },
+        }
+    }
+
+    fs::write(dst, out)?;
+    deploy::copy_meta(&fs::metadata(src)?, dst, keep)?;
+    Ok(())
+}
+
+// render - Produce the copy of a source that is actually deployed, with its
+// templates filled in and any excluded entities left out
+// Sources which need neither are deployed as they stand, without a copy
+pub fn render(symbols: &inter::Symbols, src: &Path, exclude: &glob::Exclude, keep: deploy::Preserve) -> Result<Option<Rendering>, RenderError> {
+    // A linked source is deployed as the link alone, with nothing to render
+    if keep.LINKS && fs::symlink_metadata(src)?.file_type().is_symlink() {
+        return Ok(None);
+    }
+
+    let excludes = |entry: &walkdir::DirEntry| {
+        entry.depth() > 0 && exclude.excludes(entry.path().strip_prefix(src).unwrap(), entry.path().is_dir())
+    };
+    let needed = walkdir::WalkDir::new(src).follow_links(!keep.LINKS).into_iter()
+                                           .filter_map(|e| e.ok())
+                                           .any(|e| (e.file_type().is_file() && is_template(e.path())) || excludes(&e));
+    if !needed {
+        return Ok(None);
+    }
+
+    let dir = ::std::env::temp_dir().join(format!("jann-{}-render", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+    let rendering = Rendering {
+        path: dir.join(strip(Path::new(src.file_name().unwrap()))),
+        dir,
+    };
+
+    if src.is_file() {
+        render_file(symbols, src, &rendering.path, keep)?;
+        return Ok(Some(rendering));
+    }
+
+    // The rendering is copied as a deployment would be, so that it is
+    // laid down the same way once deployed from
+    let mut dirs = vec![];
+    let walk = walkdir::WalkDir::new(src).follow_links(!keep.LINKS);
+    for entry in walk.into_iter().filter_entry(|e| !excludes(e)) {
+        let entry = entry?;
+        let path = entry.path();
+        let linked = rendering.path.join(path.strip_prefix(src).unwrap());
+        if entry.file_type().is_file() && is_template(path) {
+            render_file(symbols, path, &strip(&linked), keep)?;
+        }
+        else {
+            deploy::copy_entity(path, &linked, keep)?;
+            if entry.file_type().is_dir() {
+                dirs.push((entry.metadata()?, linked));
+            }
+        }
+    }
+    for (meta, linked) in dirs.iter().rev() {
+        deploy::copy_meta(meta, linked, keep)?;
+    }
+    Ok(Some(rendering))
+}
+
+// inline - Render the content of an inline statement as a file of the given
+// name, so that it can be deployed as any file in the bundle would be
+// Content is written as lines of text, so always ends in a newline
+pub fn inline(name: &OsStr, content: &str) -> Result<Rendering, DeployError> {
+    let dir = ::std::env::temp_dir().join(format!("jann-{}-inline", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+    let rendering = Rendering {
+        path: dir.join(name),
+        dir,
+    };
+
+    let mut text = content.to_string();
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    fs::write(&rendering.path, text)?;
+    Ok(rendering)
+}
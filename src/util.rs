@@ -1,12 +1,18 @@
 extern crate regex;
+extern crate dirs;
 
 use parse::Token;
 use std::process;
+use std::fs;
+use std::env;
+use std::path::{Path, PathBuf};
 
 pub struct Log<'src> {
     job  : String,
     lines: &'src Vec<String>,
     err_count: usize,
+    summary: Vec<(&'static str, String)>,
+    stage: Option<(PathBuf, bool)>,
 }
 
 impl<'src> Log<'src> {
@@ -15,27 +21,84 @@ impl<'src> Log<'src> {
             job: job,
             lines: lines,
             err_count: 0,
+            summary: vec![],
+            stage: None,
         }
     }
+
+    // stage - Note the stage an archive is built in, which is reported as
+    // though it were unpacked at /, and if owned, removed however the run concludes
+    pub fn stage(&mut self, stage: &Path, owned: bool) {
+        self.stage = Some((stage.to_path_buf(), owned));
+    }
+
+    // Record what became of an item, to be listed when the run concludes
+    pub fn report(&mut self, status: &'static str, item: &Path) {
+        let item = match self.stage {
+            Some((ref stage, _)) => match item.strip_prefix(stage) {
+                Ok(rel) => Path::new("/").join(rel),
+                Err(_) => item.to_path_buf(),
+            },
+            None => item.to_path_buf(),
+        };
+        self.summary.push((status, format!("{:?}", item)));
+    }
     
     fn message(&self, lvl: &str, msg: &str, hint: &str, tok: &Token) {
         println!("{}: {}", lvl, msg);
-        if tok.lno != 1 {
-            let preln = self.lines.get(tok.lno - 2).unwrap();
+        let lno = tok.val.line();
+        if lno != 1 {
+            let preln = self.lines.get(lno - 2).unwrap();
             if !preln.is_empty() {
-                println!("{:>4} | {}", tok.lno - 1, preln);
+                println!("{:>4} | {}", lno - 1, preln);
             }
         }
-        println!("{:>4} | {}", tok.lno, self.lines.get(tok.lno - 1).unwrap());
-        println!("     |{}{}", &" ".repeat(1 + tok.val.lptr), &"^".repeat((tok.val.rptr - tok.val.lptr) + 1));
+        println!("{:>4} | {}", lno, self.lines.get(lno - 1).map(|l| l.as_str()).unwrap_or(""));
+        println!("     |{}{}", &" ".repeat(1 + tok.val.col()), &"^".repeat(tok.val.width()));
         println!("hint: {}\n", hint);
     }
 
+    // Report a fatal error on a line of some file other than the Jannfile
+    pub fn file_terminal(&mut self, msg: &str, hint: &str, file: &Path, lno: usize, line: &str) -> ! {
+        println!("error: {}", msg);
+        println!("  --> {}", file.display());
+        println!("{:>4} | {}", lno, line);
+        println!("hint: {}\n", hint);
+        self.die();
+    }
+
     pub fn has_err(&self) -> bool {
         self.err_count > 0
     }
 
+    fn summarise(&self) {
+        if let Some((ref stage, true)) = self.stage {
+            let _ = fs::remove_dir_all(stage);
+        }
+        if !self.summary.is_empty() {
+            println!("\n[{}] summary", self.job);
+            for (status, item) in &self.summary {
+                println!("{:>11} {}", status, item);
+            }
+        }
+    }
+
+    // Conclude a status check, exiting with 2 if anything has drifted
+    pub fn conclude_status(&self, drifted: bool) -> ! {
+        if self.err_count > 0 {
+            self.conclude();
+        }
+        self.summarise();
+        if drifted {
+            println!("\n[{}] drifted", self.job);
+            process::exit(2);
+        }
+        println!("\n[{}] in sync", self.job);
+        process::exit(0);
+    }
+
     pub fn conclude(&self) -> ! {
+        self.summarise();
         if self.err_count == 0 {
             println!("\n[{}] success", self.job);
             process::exit(0);
@@ -66,3 +129,11 @@ impl<'src> Log<'src> {
         self.conclude()
     }
 }
+
+// Directory in which jann keeps state between runs
+pub fn state_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_STATE_HOME") {
+        Some(ref dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("jann")),
+        _ => dirs::home_dir().map(|home| home.join(".local/state/jann")),
+    }
+}